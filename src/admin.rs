@@ -0,0 +1,233 @@
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum PausableFeature {
+    Listings,
+    Offers,
+    Permissions,
+}
+
+// only creation is paused, removals, refunds and payouts always go through
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PauseState {
+    pub listings: bool,
+    pub offers: bool,
+    pub permissions: bool,
+}
+
+impl PauseState {
+    pub fn is_paused(&self, feature: PausableFeature) -> bool {
+        match feature {
+            PausableFeature::Listings => self.listings,
+            PausableFeature::Offers => self.offers,
+            PausableFeature::Permissions => self.permissions,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn owner_id(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+
+    pub fn pending_owner_id(&self) -> Option<AccountId> {
+        self.pending_owner_id.clone()
+    }
+
+    // the new owner has to accept, proposing the current owner cancels a pending transfer
+    pub fn transfer_ownership(&mut self, new_owner_id: AccountId) {
+        self.assert_owner();
+        if new_owner_id == self.owner_id {
+            self.pending_owner_id = None;
+        } else {
+            self.pending_owner_id = Some(new_owner_id);
+        }
+    }
+
+    pub fn accept_ownership(&mut self) {
+        let account_id = env::predecessor_account_id();
+        require!(
+            self.pending_owner_id.as_ref() == Some(&account_id),
+            "Only the pending owner can accept ownership"
+        );
+        self.owner_id = account_id;
+        self.pending_owner_id = None;
+    }
+
+    pub fn set_contract_metadata(&mut self, metadata: SBTPermissionsContractMetadata) {
+        self.assert_owner();
+        self.contract_metadata = metadata;
+    }
+
+    pub fn pause_state(&self) -> PauseState {
+        self.pause_state.clone()
+    }
+
+    pub fn set_paused(&mut self, feature: PausableFeature, paused: bool) {
+        self.assert_owner();
+        require!(
+            paused || self.legacy_listings.is_none(),
+            "Legacy listings must be migrated before unpausing"
+        );
+        match feature {
+            PausableFeature::Listings => self.pause_state.listings = paused,
+            PausableFeature::Offers => self.pause_state.offers = paused,
+            PausableFeature::Permissions => self.pause_state.permissions = paused,
+        }
+    }
+}
+
+impl Contract {
+    pub(crate) fn assert_not_paused(&self, feature: PausableFeature) {
+        let message = match feature {
+            PausableFeature::Listings => "Listing creation is paused",
+            PausableFeature::Offers => "Offer creation is paused",
+            PausableFeature::Permissions => "Permission creation is paused",
+        };
+        require!(!self.pause_state.is_paused(feature), message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn paused_contract(registered: &[&str], features: &[PausableFeature]) -> Contract {
+        let mut contract = setup_contract(registered);
+        set_caller(OWNER);
+        for feature in features {
+            contract.set_paused(*feature, true);
+        }
+        contract
+    }
+
+    #[test]
+    fn transfers_ownership_in_two_steps() {
+        let mut contract = setup_contract(&[]);
+        set_caller(OWNER);
+        contract.transfer_ownership(account("zed.near"));
+        assert_eq!(contract.owner_id(), account(OWNER));
+        assert_eq!(contract.pending_owner_id(), Some(account("zed.near")));
+
+        set_caller("zed.near");
+        contract.accept_ownership();
+        assert_eq!(contract.owner_id(), account("zed.near"));
+        assert_eq!(contract.pending_owner_id(), None);
+        contract.set_paused(PausableFeature::Offers, true);
+        assert!(contract.pause_state().offers);
+    }
+
+    #[test]
+    fn proposing_the_current_owner_cancels_a_transfer() {
+        let mut contract = setup_contract(&[]);
+        set_caller(OWNER);
+        contract.transfer_ownership(account("zed.near"));
+        contract.transfer_ownership(account(OWNER));
+        assert_eq!(contract.pending_owner_id(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the pending owner can accept ownership")]
+    fn only_the_pending_owner_can_accept() {
+        let mut contract = setup_contract(&[]);
+        set_caller(OWNER);
+        contract.transfer_ownership(account("zed.near"));
+
+        set_caller("eve.near");
+        contract.accept_ownership();
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the contract owner can do this")]
+    fn previous_owner_loses_admin_rights() {
+        let mut contract = setup_contract(&[]);
+        set_caller(OWNER);
+        contract.transfer_ownership(account("zed.near"));
+        set_caller("zed.near");
+        contract.accept_ownership();
+
+        set_caller(OWNER);
+        contract.set_paused(PausableFeature::Listings, true);
+    }
+
+    #[test]
+    fn owner_sets_contract_metadata() {
+        let mut contract = setup_contract(&[]);
+        set_caller(OWNER);
+        contract.set_contract_metadata(SBTPermissionsContractMetadata {
+            spec: "sbt-permissions-1.0.0".to_string(),
+            name: Some("Marketplace".to_string()),
+            symbol: None,
+            base_uri: None,
+            reference: None,
+        });
+        let metadata = contract.sbt_permissions_metadata();
+        assert_eq!(metadata.spec, "sbt-permissions-1.0.0");
+        assert_eq!(metadata.name, Some("Marketplace".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the contract owner can do this")]
+    fn others_cannot_set_contract_metadata() {
+        let mut contract = setup_contract(&[]);
+        let metadata = contract.sbt_permissions_metadata();
+        set_caller("eve.near");
+        contract.set_contract_metadata(metadata);
+    }
+
+    #[test]
+    #[should_panic(expected = "Listing creation is paused")]
+    fn rejects_listings_while_paused() {
+        let mut contract = paused_contract(&["alice.near"], &[PausableFeature::Listings]);
+        set_caller("alice.near");
+        contract.add_listing(vec![token("1")], None, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Offer creation is paused")]
+    fn rejects_offers_while_paused() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        let listing_id = add_listing(&mut contract, "alice.near", vec![token("1")], None);
+        set_caller(OWNER);
+        contract.set_paused(PausableFeature::Offers, true);
+
+        add_offer(&mut contract, "bob.near", &listing_id, 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "Permission creation is paused")]
+    fn rejects_permissions_while_paused() {
+        let mut contract = paused_contract(&["alice.near"], &[PausableFeature::Permissions]);
+        set_caller("alice.near");
+        contract.create_permission(sign_permission(permission_body(vec![token("1")], &["bob.near"], 1), 1));
+    }
+
+    #[test]
+    fn withdrawals_rejections_and_claims_work_while_paused() {
+        let mut contract = setup_contract(&["alice.near", "bob.near", "carol.near"]);
+        let listing_id = add_listing(&mut contract, "alice.near", vec![token("1")], Some(100));
+        add_offer(&mut contract, "bob.near", &listing_id, 100);
+        add_offer(&mut contract, "carol.near", &listing_id, 100);
+        contract.credit_payout(&account("alice.near"), 50, &None);
+        set_caller(OWNER);
+        for feature in [PausableFeature::Listings, PausableFeature::Offers, PausableFeature::Permissions] {
+            contract.set_paused(feature, true);
+        }
+
+        set_caller("bob.near");
+        contract.withdraw_offer(listing_id.clone());
+        set_caller("alice.near");
+        contract.reject_offer(listing_id.clone(), account("carol.near"));
+        contract.claim_payout(None);
+        contract.remove_listing(listing_id.clone());
+
+        assert!(contract.view_offers_for_listing(listing_id.clone(), None, None).is_empty());
+        assert_eq!(contract.pending_payout(account("alice.near"), None).0, 0);
+        assert!(contract.view_listing(listing_id).is_none());
+    }
+}
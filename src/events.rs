@@ -0,0 +1,158 @@
+use crate::*;
+
+pub const EVENT_STANDARD: &str = "sbt_marketplace";
+pub const EVENT_VERSION: &str = "1.0.0";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ListingRemovedEventData<'a> {
+    pub listing_id: &'a ListingId,
+    pub account_id: &'a AccountId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OfferAcceptedEventData<'a> {
+    pub offer: &'a SBTListingOffer,
+    pub lister_id: &'a AccountId,
+    pub permission_signature: &'a Signature,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PermissionEventData<'a> {
+    pub signature: &'a Signature,
+    pub grantor_id: &'a AccountId,
+    pub public_key: &'a PublicKey,
+    pub sbt_tokens: &'a Vec<SBTTokenLocator>,
+    pub accounts: &'a Vec<AccountId>,
+    pub scopes: &'a Vec<PermissionScope>,
+    pub valid_from: Option<U64>,
+    pub valid_until: Option<U64>,
+}
+
+impl<'a> PermissionEventData<'a> {
+    pub fn new(permission: &'a SBTPermission, grantor_id: &'a AccountId) -> Self {
+        Self {
+            signature: &permission.signature,
+            grantor_id,
+            public_key: &permission.public_key,
+            sbt_tokens: &permission.body.sbt_tokens,
+            accounts: &permission.body.accounts,
+            scopes: &permission.body.scopes,
+            valid_from: permission.body.valid_from,
+            valid_until: permission.body.valid_until,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PayoutEventData<'a> {
+    pub account_id: &'a AccountId,
+    pub amount: U128,
+    pub ft_contract_id: &'a Option<AccountId>,
+}
+
+pub(crate) fn emit_event<T: Serialize>(event: &str, data: T) {
+    log!(
+        "EVENT_JSON:{}",
+        json!({
+            "standard": EVENT_STANDARD,
+            "version": EVENT_VERSION,
+            "event": event,
+            "data": [data],
+        })
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::serde_json::Value;
+
+    fn events() -> Vec<Value> {
+        near_sdk::test_utils::get_logs()
+            .iter()
+            .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+            .map(|event| near_sdk::serde_json::from_str(event).unwrap())
+            .collect()
+    }
+
+    fn event_data(name: &str) -> Value {
+        let events = events();
+        let event = events.iter().find(|event| event["event"] == name).unwrap();
+        assert_eq!(event["standard"], EVENT_STANDARD);
+        assert_eq!(event["version"], EVENT_VERSION);
+        assert_eq!(event["data"].as_array().unwrap().len(), 1);
+        event["data"][0].clone()
+    }
+
+    #[test]
+    fn emits_listing_added() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let listing_id = add_listing(&mut contract, "alice.near", vec![token("1")], Some(100));
+
+        let data = event_data("listing_added");
+        assert_eq!(data["id"], listing_id.as_str());
+        assert_eq!(data["account_id"], "alice.near");
+        assert_eq!(data["tokens"][0]["token_id"], "1");
+        assert_eq!(data["price"], "100");
+        assert_eq!(data["policy"], "open");
+    }
+
+    #[test]
+    fn emits_offer_placed() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        let listing_id = add_listing(&mut contract, "alice.near", vec![token("1")], Some(100));
+        add_offer(&mut contract, "bob.near", &listing_id, 150);
+
+        let data = event_data("offer_placed");
+        assert_eq!(data["listing_id"], listing_id.as_str());
+        assert_eq!(data["offering_account_id"], "bob.near");
+        assert_eq!(data["offered_price"], "150");
+        assert_eq!(data["ft_contract_id"], Value::Null);
+    }
+
+    #[test]
+    fn emits_permission_created() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let permission = sign_permission(permission_body(vec![token("1")], &["bob.near"], 1), 1);
+        grant_permission(&mut contract, "alice.near", &permission);
+
+        let data = event_data("permission_created");
+        assert_eq!(data["signature"], permission.signature.as_str());
+        assert_eq!(data["grantor_id"], "alice.near");
+        assert_eq!(data["accounts"], json!(["bob.near"]));
+        assert_eq!(data["scopes"], json!(["full"]));
+        assert_eq!(data["valid_until"], Value::Null);
+    }
+
+    #[test]
+    fn emits_payout_credited_and_claimed() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        let listing_id = add_listing(&mut contract, "alice.near", vec![token("1")], Some(1_000));
+        add_offer(&mut contract, "bob.near", &listing_id, 1_000);
+        let permission = sign_permission(permission_body(vec![token("1")], &["bob.near"], 1), 1);
+        accept_offer(&mut contract, "alice.near", &listing_id, &permission);
+
+        let accepted = event_data("offer_accepted");
+        assert_eq!(accepted["lister_id"], "alice.near");
+        assert_eq!(accepted["permission_signature"], permission.signature.as_str());
+        let credited: Vec<Value> = events()
+            .into_iter()
+            .filter(|event| event["event"] == "payout_credited")
+            .map(|event| event["data"][0].clone())
+            .collect();
+        assert!(credited.contains(&json!({
+            "account_id": "alice.near", "amount": "800", "ft_contract_id": null
+        })));
+
+        set_caller("alice.near");
+        contract.claim_payout(None);
+        let claimed = event_data("payout_claimed");
+        assert_eq!(claimed["account_id"], "alice.near");
+        assert_eq!(claimed["amount"], "800");
+    }
+}
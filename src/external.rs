@@ -0,0 +1,42 @@
+use crate::*;
+
+#[ext_contract(ext_oracle)]
+pub trait SBTMarketplaceOracle {
+    fn request_validation(&mut self,
+        to_validate_account: AccountId, to_validate_public_key: String, callback_message: Option<String>);
+}
+
+// NEP-393 token ids are numeric, metadata is not needed here and left out
+pub type RegistryTokenId = u64;
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SBTToken {
+    pub token: RegistryTokenId,
+    pub owner: AccountId,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OwnedSBTToken {
+    pub token: RegistryTokenId,
+}
+
+// NEP-393 registry queries
+#[ext_contract(ext_sbt)]
+pub trait SBTRegistry {
+    fn sbt(&self, issuer: AccountId, token: RegistryTokenId) -> Option<SBTToken>;
+
+    fn sbt_tokens_by_owner(&self,
+        account: AccountId,
+        issuer: Option<AccountId>,
+        from_class: Option<u64>,
+        limit: Option<u32>,
+        with_expired: bool,
+    ) -> Vec<(AccountId, Vec<OwnedSBTToken>)>;
+}
+
+#[ext_contract(ext_ft)]
+pub trait FungibleToken {
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+}
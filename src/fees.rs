@@ -0,0 +1,238 @@
+use crate::*;
+
+pub const TOTAL_BPS: u16 = 10_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeSchedule {
+    pub lister_bps: u16,
+    pub provider_bps: u16,
+    pub marketplace_bps: u16,
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        Self {
+            lister_bps: 8_000,
+            provider_bps: 1_500,
+            marketplace_bps: 500,
+        }
+    }
+}
+
+impl FeeSchedule {
+    pub fn assert_valid(&self) {
+        require!(
+            self.lister_bps as u32 + self.provider_bps as u32 + self.marketplace_bps as u32 == TOTAL_BPS as u32,
+            "Fee schedule must add up to 10000 basis points"
+        );
+    }
+}
+
+pub(crate) struct PaymentSplit {
+    pub lister_amount: Balance,
+    pub provider_amounts: Vec<(AccountId, Balance)>,
+    pub marketplace_amount: Balance,
+    pub dust: Balance,
+}
+
+pub(crate) fn bps_of(amount: u128, bps: u16) -> u128 {
+    amount * bps as u128 / TOTAL_BPS as u128
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn fee_schedule(&self) -> FeeSchedule {
+        self.fee_schedule.clone()
+    }
+
+    pub fn set_fee_schedule(&mut self, fee_schedule: FeeSchedule) {
+        self.assert_owner();
+        fee_schedule.assert_valid();
+        self.fee_schedule = fee_schedule;
+    }
+
+    pub fn provider_share(&self, sbt_contract_id: AccountId) -> u16 {
+        self.effective_provider_share(&sbt_contract_id)
+    }
+
+    // called by an SBT issuer contract to set its own cut, capped by the schedule's provider share
+    pub fn register_provider_share(&mut self, share_bps: u16) {
+        require!(
+            share_bps <= self.fee_schedule.provider_bps,
+            "Provider share cannot exceed the provider share of the fee schedule"
+        );
+        self.provider_shares.insert(&env::predecessor_account_id(), &share_bps);
+    }
+
+    pub fn unregister_provider_share(&mut self) {
+        self.provider_shares.remove(&env::predecessor_account_id());
+    }
+
+    pub fn provider_weight(&self, sbt_contract_id: AccountId) -> u16 {
+        self.provider_weights.get(&sbt_contract_id).unwrap_or(1)
+    }
+
+    // weight applied per listed token of the issuer, defaults to 1
+    pub fn set_provider_weight(&mut self, sbt_contract_id: AccountId, weight: Option<u16>) {
+        self.assert_owner();
+        match weight {
+            Some(weight) => {
+                require!(weight > 0, "Provider weight must be positive");
+                self.provider_weights.insert(&sbt_contract_id, &weight);
+            }
+            None => {
+                self.provider_weights.remove(&sbt_contract_id);
+            }
+        }
+    }
+
+    fn effective_provider_share(&self, sbt_contract_id: &AccountId) -> u16 {
+        self.provider_shares
+            .get(sbt_contract_id)
+            .map(|share| share.min(self.fee_schedule.provider_bps))
+            .unwrap_or(self.fee_schedule.provider_bps)
+    }
+
+    pub(crate) fn split_payment(&self, listing: &SBTListing, price: Balance) -> PaymentSplit {
+        let mut provider_weights: Vec<(AccountId, u128)> = Vec::new();
+        for token in listing.tokens.iter() {
+            let weight = self.provider_weight(token.sbt_contract_id.clone()) as u128;
+            match provider_weights.iter_mut().find(|(provider, _)| *provider == token.sbt_contract_id) {
+                Some((_, total)) => *total += weight,
+                None => provider_weights.push((token.sbt_contract_id.clone(), weight)),
+            }
+        }
+        let total_weight: u128 = provider_weights.iter().map(|(_, weight)| weight).sum();
+
+        let provider_pool = bps_of(price, self.fee_schedule.provider_bps);
+        let mut waived: Balance = 0;
+        let provider_amounts: Vec<(AccountId, Balance)> = provider_weights
+            .into_iter()
+            .map(|(provider, weight)| {
+                let slice = provider_pool * weight / total_weight;
+                let share = self.effective_provider_share(&provider);
+                let amount = if share == self.fee_schedule.provider_bps {
+                    slice
+                } else {
+                    slice * share as u128 / self.fee_schedule.provider_bps as u128
+                };
+                waived += slice - amount;
+                (provider, amount)
+            })
+            .collect();
+
+        // shares waived by providers go to the lister
+        let lister_amount = bps_of(price, self.fee_schedule.lister_bps) + waived;
+        let marketplace_amount = bps_of(price, self.fee_schedule.marketplace_bps);
+        let provider_total: Balance = provider_amounts.iter().map(|(_, amount)| amount).sum();
+        PaymentSplit {
+            lister_amount,
+            provider_amounts,
+            marketplace_amount,
+            dust: price - lister_amount - marketplace_amount - provider_total,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn listing(tokens: Vec<SBTTokenLocator>) -> SBTListing {
+        SBTListing {
+            id: Contract::get_listing_id(&tokens, &account("alice.near")),
+            account_id: account("alice.near"),
+            tokens,
+            price: Some(U128(10_000)),
+            ft_contract_id: None,
+            policy: ListingPolicy::Open,
+            sold: false,
+        }
+    }
+
+    #[test]
+    fn splits_payment_by_the_default_schedule() {
+        let contract = setup_contract(&[]);
+        let split = contract.split_payment(&listing(vec![token("1")]), 10_000);
+        assert_eq!(split.lister_amount, 8_000);
+        assert_eq!(split.provider_amounts, vec![(account(SBT_CONTRACT), 1_500)]);
+        assert_eq!(split.marketplace_amount, 500);
+        assert_eq!(split.dust, 0);
+    }
+
+    #[test]
+    fn splits_payment_by_a_custom_schedule() {
+        let mut contract = setup_contract(&[]);
+        set_caller(OWNER);
+        contract.set_fee_schedule(FeeSchedule { lister_bps: 9_000, provider_bps: 700, marketplace_bps: 300 });
+        let split = contract.split_payment(&listing(vec![token("1")]), 10_000);
+        assert_eq!(split.lister_amount, 9_000);
+        assert_eq!(split.provider_amounts, vec![(account(SBT_CONTRACT), 700)]);
+        assert_eq!(split.marketplace_amount, 300);
+    }
+
+    #[test]
+    #[should_panic(expected = "Fee schedule must add up to 10000 basis points")]
+    fn rejects_schedule_not_adding_up() {
+        let mut contract = setup_contract(&[]);
+        set_caller(OWNER);
+        contract.set_fee_schedule(FeeSchedule { lister_bps: 9_000, provider_bps: 1_500, marketplace_bps: 500 });
+    }
+
+    fn issuer_token(sbt_contract_id: &str, token_id: &str) -> SBTTokenLocator {
+        SBTTokenLocator { sbt_contract_id: account(sbt_contract_id), ..token(token_id) }
+    }
+
+    fn mixed_listing() -> SBTListing {
+        listing(vec![token("1"), token("2"), issuer_token("other.near", "1")])
+    }
+
+    #[test]
+    fn pays_each_provider_once_by_token_count() {
+        let contract = setup_contract(&[]);
+        let split = contract.split_payment(&mixed_listing(), 10_000);
+        assert_eq!(
+            split.provider_amounts,
+            vec![(account(SBT_CONTRACT), 1_000), (account("other.near"), 500)]
+        );
+        assert_eq!(split.lister_amount, 8_000);
+    }
+
+    #[test]
+    fn applies_provider_weights() {
+        let mut contract = setup_contract(&[]);
+        set_caller(OWNER);
+        contract.set_provider_weight(account("other.near"), Some(4));
+        let split = contract.split_payment(&mixed_listing(), 10_000);
+        assert_eq!(
+            split.provider_amounts,
+            vec![(account(SBT_CONTRACT), 500), (account("other.near"), 1_000)]
+        );
+    }
+
+    #[test]
+    fn gives_waived_provider_shares_to_the_lister() {
+        let mut contract = setup_contract(&[]);
+        set_caller("other.near");
+        contract.register_provider_share(750);
+        let split = contract.split_payment(&mixed_listing(), 10_000);
+        assert_eq!(
+            split.provider_amounts,
+            vec![(account(SBT_CONTRACT), 1_000), (account("other.near"), 250)]
+        );
+        assert_eq!(split.lister_amount, 8_250);
+        assert_eq!(split.dust, 0);
+    }
+
+    #[test]
+    fn keeps_rounding_dust_separate() {
+        let contract = setup_contract(&[]);
+        let split = contract.split_payment(&mixed_listing(), 10_007);
+        let provider_total: Balance = split.provider_amounts.iter().map(|(_, amount)| amount).sum();
+        assert_eq!(provider_total, 1_500);
+        assert_eq!(split.dust, 2);
+        assert_eq!(split.lister_amount + split.marketplace_amount + provider_total + split.dust, 10_007);
+    }
+}
@@ -1,26 +1,41 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{LookupMap, Vector, UnorderedMap, UnorderedSet};
+use near_sdk::collections::{LookupMap, TreeMap, Vector, UnorderedMap, UnorderedSet};
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::json_types::{U128};
-use near_sdk::{env, near_bindgen, require, Promise, AccountId, PublicKey, BorshStorageKey};
-use std::hash::{Hash, Hasher};
-use std::collections::hash_map::DefaultHasher;
+use near_sdk::serde_json::json;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{env, ext_contract, log, near_bindgen, require, Gas, Promise, PromiseError, PromiseOrValue, PromiseResult, AccountId, Balance, CurveType, PublicKey, StorageUsage, BorshStorageKey};
+use std::collections::HashSet;
 
-//pub use crate::external::*;
+pub use crate::admin::*;
+pub use crate::events::*;
+pub use crate::external::*;
+pub use crate::fees::*;
 pub use crate::permissions::*;
+pub use crate::storage::*;
 pub use crate::listings::*;
+pub use crate::migration::*;
 pub use crate::offers::*;
 
-//mod external;
+mod admin;
+mod events;
+mod external;
+mod fees;
+mod treasury;
 mod permissions;
+mod storage;
 mod listings;
+mod migration;
 mod offers;
+#[cfg(test)]
+mod test_utils;
 
 pub type TokenId = String;
 pub type Signature = String;
 pub type ListingId = String;
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Hash)]
+pub const TGAS: u64 = 1_000_000_000_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(crate = "near_sdk::serde")]
 pub struct SBTTokenLocator {
     pub chain_id: String,
@@ -30,15 +45,56 @@ pub struct SBTTokenLocator {
 
 impl SBTTokenLocator {
     pub fn contract_key(&self) -> (String, AccountId) {
-        return (self.chain_id.clone(), self.sbt_contract_id.clone());
+        (self.chain_id.clone(), self.sbt_contract_id.clone())
+    }
+
+    pub fn registry_token_id(&self) -> Option<RegistryTokenId> {
+        self.token_id.parse().ok()
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionScope {
+    Full,
+    Metadata,
+    Ownership,
+    Attribute(String),
+}
+
+impl PermissionScope {
+    pub fn covers(&self, scope: &PermissionScope) -> bool {
+        match self {
+            PermissionScope::Full => true,
+            PermissionScope::Metadata => matches!(scope, PermissionScope::Metadata | PermissionScope::Attribute(_)),
+            _ => self == scope,
+        }
     }
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct PermissionBody {
+    pub marketplace_contract_id: AccountId,
+    pub chain_id: String,
+    pub nonce: U64,
     pub sbt_tokens: Vec<SBTTokenLocator>,
     pub accounts: Vec<AccountId>,
+    pub scopes: Vec<PermissionScope>,
+    pub valid_from: Option<U64>,
+    pub valid_until: Option<U64>,
+}
+
+impl PermissionBody {
+    pub fn is_active(&self, timestamp: u64) -> bool {
+        self.valid_from.is_none_or(|from| timestamp >= u64::from(from))
+            && self.valid_until.is_none_or(|until| timestamp < u64::from(until))
+    }
+
+    pub fn grants(&self, scope: &PermissionScope) -> bool {
+        self.scopes.iter().any(|granted| granted.covers(scope))
+    }
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
@@ -49,6 +105,15 @@ pub struct SBTPermission {
     pub public_key: PublicKey,
 }
 
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingPermission {
+    pub permission: SBTPermission,
+    pub account_id: AccountId,
+    pub listing_id: Option<ListingId>,
+    pub requested_at: U64,
+}
+
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub struct SBTPermissionsContractMetadata {
@@ -59,13 +124,26 @@ pub struct SBTPermissionsContractMetadata {
     pub reference: Option<String>,
 }
 
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum ListingPolicy {
+    // listing stays open after an accepted offer, competing offers are kept
+    Open,
+    // listing is marked sold after an accepted offer, competing offers are refunded
+    SingleSale,
+}
+
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct SBTListing {
     pub id: ListingId,
     pub account_id: AccountId,
     pub tokens: Vec<SBTTokenLocator>,
-    pub price: Option<U128>
+    pub price: Option<U128>,
+    pub ft_contract_id: Option<AccountId>,
+    pub policy: ListingPolicy,
+    pub sold: bool
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
@@ -73,7 +151,15 @@ pub struct SBTListing {
 pub struct SBTListingOffer {
     pub listing_id: ListingId,
     pub offering_account_id: AccountId,
-    pub offered_price: Option<U128>
+    pub offered_price: Option<U128>,
+    pub ft_contract_id: Option<AccountId>
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SBTListingOfferView {
+    pub offer: SBTListingOffer,
+    pub listing: SBTListing,
 }
 
 #[derive(BorshSerialize, BorshStorageKey)]
@@ -102,29 +188,92 @@ enum StorageKey {
     OffersForAccount,
     OffersForAccountOffers {
         account_id: AccountId
+    },
+    PendingPermissions,
+    PermissionGrantors,
+    PermissionsByExpiry,
+    PermissionsForAccount,
+    PermissionsForAccountSignatures {
+        account_id: AccountId
+    },
+    PermissionNonces,
+    ProviderShares,
+    ProviderWeights,
+    PendingPayouts,
+    TreasuryBalances,
+    StorageAccounts,
+    ListingsForContract,
+    ListingsForContractListings {
+        chain_id: String,
+        sbt_contract_id: AccountId,
+    },
+    PendingPermissionNonces,
+    MigratedPermissionsBySignature,
+    MigratedListingsById,
+    MigratedOffersById,
+    OffersForListing,
+    OffersForListingAccounts {
+        listing_id: ListingId
     }
 }
 
+// page over keys before loading values, skip only stays cheap on the collections' own iterators
+pub(crate) fn paginate<T>(items: impl Iterator<Item = T>, from_index: Option<u64>, limit: Option<u64>) -> Vec<T> {
+    let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+    require!(limit != 0, "Cannot provide limit of 0.");
+    let start_index = from_index.unwrap_or_default() as usize;
+
+    items.skip(start_index).take(limit).collect()
+}
+
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct Contract {
     contract_metadata: SBTPermissionsContractMetadata,
-    //oracle_account_id: AccountId,
+    chain_id: String,
+    oracle_account_id: AccountId,
     owner_id: AccountId,
+    pending_owner_id: Option<AccountId>,
+    pause_state: PauseState,
     permissions_by_signature: LookupMap<Signature, SBTPermission>,
+    pending_permissions: UnorderedMap<Signature, PendingPermission>,
+    permission_grantors: LookupMap<Signature, AccountId>,
+    permissions_by_expiry: TreeMap<(u64, Signature), ()>,
+    permissions_for_account: LookupMap<AccountId, UnorderedSet<Signature>>,
+    permission_nonces: LookupMap<PublicKey, u64>,
+    pending_permission_nonces: LookupMap<(PublicKey, u64), Signature>,
     permissions_for_token: LookupMap<(String, AccountId), LookupMap<TokenId, Vector<Signature>>>,
     listings_by_id: UnorderedMap<ListingId, SBTListing>,
     listings_for_account: LookupMap<AccountId, Vector<ListingId>>,
+    listings_for_contract: LookupMap<(String, AccountId), UnorderedSet<ListingId>>,
     offers_by_id: UnorderedMap<(ListingId, AccountId), SBTListingOffer>,
     offers_by_account: LookupMap<AccountId, UnorderedSet<ListingId>>,
-    offers_for_account: LookupMap<AccountId, UnorderedSet<(ListingId, AccountId)>>
+    offers_for_account: LookupMap<AccountId, UnorderedSet<(ListingId, AccountId)>>,
+    offers_for_listing: LookupMap<ListingId, UnorderedSet<AccountId>>,
+    fee_schedule: FeeSchedule,
+    provider_shares: LookupMap<AccountId, u16>,
+    provider_weights: LookupMap<AccountId, u16>,
+    // balances below are keyed by FT contract, None for NEAR
+    treasury_balances: LookupMap<Option<AccountId>, Balance>,
+    pending_payouts: LookupMap<(AccountId, Option<AccountId>), Balance>,
+    // NEAR held for open offers and unclaimed payouts
+    escrow_balance: Balance,
+    pending_payout_total: Balance,
+    storage_accounts: LookupMap<AccountId, StorageAccount>,
+    storage_deposit_total: Balance,
+    // only set on deployments migrated from the original layout, until migrate_listing_ids is done
+    legacy_listings: Option<UnorderedMap<ListingId, LegacySBTListing>>,
+    legacy_offers: Option<UnorderedMap<(ListingId, AccountId), LegacySBTListingOffer>>
 }
 
 impl Default for Contract {
     fn default() -> Self {
         Self {
             owner_id: "default".parse().unwrap(),
-            //oracle_account_id: "oracle_contract".parse().unwrap(),
+            pending_owner_id: None,
+            pause_state: PauseState::default(),
+            chain_id: "testnet".to_string(),
+            oracle_account_id: "oracle_contract".parse().unwrap(),
             contract_metadata: SBTPermissionsContractMetadata {
                 spec: "sbt-permissions-0.0.1".to_string(),
                 name: Some("sbt-marketplace-nearcon".parse().unwrap()),
@@ -133,12 +282,31 @@ impl Default for Contract {
                 reference: None,
             },
             permissions_by_signature: LookupMap::new(StorageKey::PermissionsBySignature),
+            pending_permissions: UnorderedMap::new(StorageKey::PendingPermissions),
+            permission_grantors: LookupMap::new(StorageKey::PermissionGrantors),
+            permissions_by_expiry: TreeMap::new(StorageKey::PermissionsByExpiry),
+            permissions_for_account: LookupMap::new(StorageKey::PermissionsForAccount),
+            permission_nonces: LookupMap::new(StorageKey::PermissionNonces),
+            pending_permission_nonces: LookupMap::new(StorageKey::PendingPermissionNonces),
             permissions_for_token: LookupMap::new(StorageKey::PermissionsForToken),
             listings_by_id: UnorderedMap::new(StorageKey::ListingsById),
             listings_for_account: LookupMap::new(StorageKey::ListingsByAccount),
+            listings_for_contract: LookupMap::new(StorageKey::ListingsForContract),
             offers_by_id: UnorderedMap::new(StorageKey::OffersById),
             offers_by_account: LookupMap::new(StorageKey::OffersByAccount),
-            offers_for_account: LookupMap::new(StorageKey::OffersForAccount)
+            offers_for_account: LookupMap::new(StorageKey::OffersForAccount),
+            offers_for_listing: LookupMap::new(StorageKey::OffersForListing),
+            fee_schedule: FeeSchedule::default(),
+            provider_shares: LookupMap::new(StorageKey::ProviderShares),
+            provider_weights: LookupMap::new(StorageKey::ProviderWeights),
+            treasury_balances: LookupMap::new(StorageKey::TreasuryBalances),
+            pending_payouts: LookupMap::new(StorageKey::PendingPayouts),
+            escrow_balance: 0,
+            pending_payout_total: 0,
+            storage_accounts: LookupMap::new(StorageKey::StorageAccounts),
+            storage_deposit_total: 0,
+            legacy_listings: None,
+            legacy_offers: None
         }
     }
 }
@@ -148,20 +316,49 @@ impl Contract {
     #[init]
     pub fn new(
         owner_id: AccountId,
-        //oracle_account_id: AccountId,
+        chain_id: String,
+        oracle_account_id: AccountId,
         metadata: SBTPermissionsContractMetadata
     ) -> Self {
         Self {
-            owner_id: owner_id,
-            //oracle_account_id: oracle_account_id,
+            owner_id,
+            pending_owner_id: None,
+            pause_state: PauseState::default(),
+            chain_id,
+            oracle_account_id,
             contract_metadata: metadata,
             permissions_by_signature: LookupMap::new(StorageKey::PermissionsBySignature),
+            pending_permissions: UnorderedMap::new(StorageKey::PendingPermissions),
+            permission_grantors: LookupMap::new(StorageKey::PermissionGrantors),
+            permissions_by_expiry: TreeMap::new(StorageKey::PermissionsByExpiry),
+            permissions_for_account: LookupMap::new(StorageKey::PermissionsForAccount),
+            permission_nonces: LookupMap::new(StorageKey::PermissionNonces),
+            pending_permission_nonces: LookupMap::new(StorageKey::PendingPermissionNonces),
             permissions_for_token: LookupMap::new(StorageKey::PermissionsForToken),
             listings_by_id: UnorderedMap::new(StorageKey::ListingsById),
             listings_for_account: LookupMap::new(StorageKey::ListingsByAccount),
+            listings_for_contract: LookupMap::new(StorageKey::ListingsForContract),
             offers_by_id: UnorderedMap::new(StorageKey::OffersById),
             offers_by_account: LookupMap::new(StorageKey::OffersByAccount),
-            offers_for_account: LookupMap::new(StorageKey::OffersForAccount)
+            offers_for_account: LookupMap::new(StorageKey::OffersForAccount),
+            offers_for_listing: LookupMap::new(StorageKey::OffersForListing),
+            fee_schedule: FeeSchedule::default(),
+            provider_shares: LookupMap::new(StorageKey::ProviderShares),
+            provider_weights: LookupMap::new(StorageKey::ProviderWeights),
+            treasury_balances: LookupMap::new(StorageKey::TreasuryBalances),
+            pending_payouts: LookupMap::new(StorageKey::PendingPayouts),
+            escrow_balance: 0,
+            pending_payout_total: 0,
+            storage_accounts: LookupMap::new(StorageKey::StorageAccounts),
+            storage_deposit_total: 0,
+            legacy_listings: None,
+            legacy_offers: None
         }
     }
-}
\ No newline at end of file
+}
+
+impl Contract {
+    pub(crate) fn assert_owner(&self) {
+        require!(env::predecessor_account_id() == self.owner_id, "Only the contract owner can do this");
+    }
+}
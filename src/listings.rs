@@ -1,58 +1,438 @@
 use crate::*;
 
-// TODO: update/remove listing
+pub trait SBTMarketplaceListings {
+    fn view_listings(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<SBTListing>;
 
-trait SBTMarketplaceListings {
-    fn view_listings(&self) -> Vec<SBTListing>;
+    fn view_listings_by_account(&self,
+        account_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<SBTListing>;
+
+    fn view_listing(&self, listing_id: ListingId) -> Option<SBTListing>;
+
+    fn listings_count(&self) -> u64;
+
+    fn view_listings_for_contract(&self,
+        chain_id: String,
+        sbt_contract_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<SBTListing>;
+
+    fn view_listings_for_token(&self,
+        token: SBTTokenLocator,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<SBTListing>;
 
     fn add_listing(&mut self,
         tokens: Vec<SBTTokenLocator>,
         price: Option<U128>,
-    ) -> ListingId;
+        policy: Option<ListingPolicy>,
+        ft_contract_id: Option<AccountId>,
+    ) -> Promise;
+
+    fn update_listing_price(&mut self, listing_id: ListingId, price: Option<U128>);
+
+    fn remove_listing(&mut self, listing_id: ListingId);
 }
 
 #[near_bindgen]
 impl SBTMarketplaceListings for Contract {
-    fn view_listings(&self) -> Vec<SBTListing> {
-        return self.listings_by_id.values().collect();
+    fn view_listings(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<SBTListing> {
+        paginate(self.listings_by_id.values(), from_index, limit)
+    }
+
+    fn view_listings_by_account(&self,
+        account_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<SBTListing> {
+        match self.listings_for_account.get(&account_id) {
+            Some(account_listings) => paginate(account_listings.iter(), from_index, limit)
+                .into_iter()
+                .map(|id| self.listings_by_id.get(&id).unwrap())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn view_listing(&self, listing_id: ListingId) -> Option<SBTListing> {
+        self.listings_by_id.get(&listing_id)
+    }
+
+    fn listings_count(&self) -> u64 {
+        self.listings_by_id.len()
+    }
+
+    fn view_listings_for_contract(&self,
+        chain_id: String,
+        sbt_contract_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<SBTListing> {
+        match self.listings_for_contract.get(&(chain_id, sbt_contract_id)) {
+            Some(contract_listings) => paginate(contract_listings.iter(), from_index, limit)
+                .into_iter()
+                .map(|id| self.listings_by_id.get(&id).unwrap())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // pages over the issuer's listings, so listings of other tokens leave a page short rather than loading them all
+    fn view_listings_for_token(&self,
+        token: SBTTokenLocator,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<SBTListing> {
+        match self.listings_for_contract.get(&token.contract_key()) {
+            Some(contract_listings) => paginate(contract_listings.iter(), from_index, limit)
+                .into_iter()
+                .map(|id| self.listings_by_id.get(&id).unwrap())
+                .filter(|listing| {
+                    listing.tokens.iter().any(|listed| {
+                        listed == &token || (listed.token_id == "*" && listed.contract_key() == token.contract_key())
+                    })
+                })
+                .collect(),
+            None => Vec::new(),
+        }
     }
 
     fn add_listing(&mut self,
         tokens: Vec<SBTTokenLocator>,
-        price: Option<U128>
-    ) -> ListingId {
-        require!(tokens.len() > 0, "Listing must include at least 1 token");
+        price: Option<U128>,
+        policy: Option<ListingPolicy>,
+        ft_contract_id: Option<AccountId>
+    ) -> Promise {
+        self.assert_not_paused(PausableFeature::Listings);
+        require!(!tokens.is_empty(), "Listing must include at least 1 token");
+        let unique_tokens: HashSet<&SBTTokenLocator> = tokens.iter().collect();
+        require!(unique_tokens.len() == tokens.len(), "Listing includes a token more than once");
 
         let account_id = env::predecessor_account_id();
+        self.assert_storage_registered(&account_id);
         let id = Self::get_listing_id(&tokens, &account_id);
         require!(self.listings_by_id.get(&id).is_none(), "Listing for this token set already exists");
 
-        // TODO check that the SBTs are owned by the signer account
-        
+        for token in tokens.iter() {
+            require!(token.chain_id == self.chain_id, "Only SBTs on this chain can be listed");
+            require!(
+                token.token_id == "*" || token.registry_token_id().is_some(),
+                "SBT token ids must be numeric NEP-393 ids or *"
+            );
+        }
+
         let listing = SBTListing {
-            id: id.clone(),
+            id,
             account_id: account_id.clone(),
             tokens: tokens.clone(),
-            price: price.clone()
+            price,
+            ft_contract_id,
+            policy: policy.unwrap_or(ListingPolicy::Open),
+            sold: false
         };
 
-        self.listings_by_id.insert(&id.clone(), &listing);
+        Self::ownership_checks(&tokens, &account_id)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(10 * TGAS))
+                    .on_listing_ownership_verified(listing),
+            )
+    }
+
+    fn update_listing_price(&mut self, listing_id: ListingId, price: Option<U128>) {
+        let mut listing = self.owned_listing(&listing_id);
+        listing.price = price;
+        let initial_storage = env::storage_usage();
+        self.listings_by_id.insert(&listing_id, &listing);
+        self.charge_storage(&listing.account_id, initial_storage, false);
+        emit_event("listing_updated", &listing);
+    }
+
+    fn remove_listing(&mut self, listing_id: ListingId) {
+        let listing = self.owned_listing(&listing_id);
+
+        for offering_account in self.offering_accounts(&listing) {
+            let offer = self.remove_offer(&listing, &offering_account);
+            emit_event("offer_rejected", &offer);
+            self.credit_refund(&offer);
+        }
+
+        let initial_storage = env::storage_usage();
+        self.listings_by_id.remove(&listing_id);
+        let mut accounts_listings = self.listings_for_account.get(&listing.account_id).unwrap();
+        if let Some(index) = accounts_listings.iter().position(|id| id == listing_id) {
+            accounts_listings.swap_remove(index as u64);
+        }
+        if accounts_listings.is_empty() {
+            self.listings_for_account.remove(&listing.account_id);
+        } else {
+            self.listings_for_account.insert(&listing.account_id, &accounts_listings);
+        }
+        self.unindex_listing_contracts(&listing_id, &listing.tokens);
+        self.charge_storage(&listing.account_id, initial_storage, false);
+        emit_event(
+            "listing_removed",
+            ListingRemovedEventData { listing_id: &listing_id, account_id: &listing.account_id },
+        );
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub(crate) fn owned_listing(&self, listing_id: &ListingId) -> SBTListing {
+        let found = self.listings_by_id.get(listing_id);
+        require!(found.is_some(), "Listing does not exist");
+        let listing = found.unwrap();
+        require!(listing.account_id == env::predecessor_account_id(), "Only the listing owner can do this");
+        listing
+    }
+
+    // sha256 over the sorted tokens and the lister, so token order does not change the id
+    pub(crate) fn get_listing_id(tokens: &[SBTTokenLocator], account: &AccountId) -> ListingId {
+        let mut sorted_tokens = tokens.to_vec();
+        sorted_tokens.sort();
+        let encoded = (sorted_tokens, account).try_to_vec().unwrap();
+        near_sdk::bs58::encode(env::sha256(&encoded)).into_string()
+    }
+
+    // one NEP-393 query per token, a wildcard token asks for any token of that issuer owned by the account;
+    // issuer contracts are queried as their own registry
+    pub(crate) fn ownership_checks(tokens: &[SBTTokenLocator], account_id: &AccountId) -> Promise {
+        tokens
+            .iter()
+            .map(|token| {
+                let registry = ext_sbt::ext(token.sbt_contract_id.clone())
+                    .with_static_gas(Gas(5 * TGAS));
+                if token.token_id == "*" {
+                    registry.sbt_tokens_by_owner(account_id.clone(), Some(token.sbt_contract_id.clone()), None, Some(1), false)
+                } else {
+                    registry.sbt(token.sbt_contract_id.clone(), token.registry_token_id().unwrap())
+                }
+            })
+            .reduce(|all, next| all.and(next))
+            .unwrap()
+    }
+
+    pub(crate) fn token_owned(result_index: u64, token: &SBTTokenLocator, account_id: &AccountId) -> bool {
+        match env::promise_result(result_index) {
+            PromiseResult::Successful(value) => {
+                if token.token_id == "*" {
+                    near_sdk::serde_json::from_slice::<Vec<(AccountId, Vec<OwnedSBTToken>)>>(&value)
+                        .map(|owned| {
+                            owned
+                                .iter()
+                                .any(|(issuer, tokens)| *issuer == token.sbt_contract_id && !tokens.is_empty())
+                        })
+                        .unwrap_or(false)
+                } else {
+                    near_sdk::serde_json::from_slice::<Option<SBTToken>>(&value)
+                        .map(|found| {
+                            found.is_some_and(|t| t.owner == *account_id && Some(t.token) == token.registry_token_id())
+                        })
+                        .unwrap_or(false)
+                }
+            }
+            _ => false,
+        }
+    }
+
+    #[private]
+    pub fn on_listing_ownership_verified(&mut self, listing: SBTListing) -> ListingId {
+        require!(
+            env::promise_results_count() == listing.tokens.len() as u64,
+            "Unexpected number of ownership results"
+        );
+        require!(self.listings_by_id.get(&listing.id).is_none(), "Listing for this token set already exists");
+
+        for (index, token) in listing.tokens.iter().enumerate() {
+            require!(
+                Self::token_owned(index as u64, token, &listing.account_id),
+                format!(
+                    "SBT {} on {} is not owned by {}",
+                    token.token_id, token.sbt_contract_id, listing.account_id
+                )
+            );
+        }
+
+        self.insert_listing(&listing, false);
+        emit_event("listing_added", &listing);
+        listing.id
+    }
+
+    pub(crate) fn insert_listing(&mut self, listing: &SBTListing, allow_overdraft: bool) {
+        let account_id = &listing.account_id;
+        let initial_storage = env::storage_usage();
+        self.listings_by_id.insert(&listing.id, listing);
         let mut accounts_listings = self
             .listings_for_account
-            .get(&account_id)
+            .get(account_id)
             .unwrap_or(Vector::new(StorageKey::ListingsForAccount{account_id: account_id.clone()}));
         accounts_listings.push(&listing.id);
-        self.listings_for_account.insert(&account_id, &accounts_listings);
-        id
+        self.listings_for_account.insert(account_id, &accounts_listings);
+        self.index_listing_contracts(listing);
+        self.charge_storage(account_id, initial_storage, allow_overdraft);
+    }
+
+    fn index_listing_contracts(&mut self, listing: &SBTListing) {
+        let contract_keys: HashSet<(String, AccountId)> = listing.tokens.iter().map(|t| t.contract_key()).collect();
+        for (chain_id, sbt_contract_id) in contract_keys {
+            let key = (chain_id.clone(), sbt_contract_id.clone());
+            let mut contract_listings = self
+                .listings_for_contract
+                .get(&key)
+                .unwrap_or(UnorderedSet::new(StorageKey::ListingsForContractListings{chain_id, sbt_contract_id}));
+            contract_listings.insert(&listing.id);
+            self.listings_for_contract.insert(&key, &contract_listings);
+        }
+    }
+
+    fn unindex_listing_contracts(&mut self, listing_id: &ListingId, tokens: &[SBTTokenLocator]) {
+        let contract_keys: HashSet<(String, AccountId)> = tokens.iter().map(|t| t.contract_key()).collect();
+        for key in contract_keys {
+            if let Some(mut contract_listings) = self.listings_for_contract.get(&key) {
+                contract_listings.remove(listing_id);
+                if contract_listings.is_empty() {
+                    self.listings_for_contract.remove(&key);
+                } else {
+                    self.listings_for_contract.insert(&key, &contract_listings);
+                }
+            }
+        }
     }
 }
 
-#[near_bindgen]
-impl Contract {
-    fn get_listing_id(tokens: &Vec<SBTTokenLocator>, account: &AccountId) -> ListingId {
-        let mut hasher = DefaultHasher::new();
-        tokens.hash(&mut hasher);
-        account.hash(&mut hasher);
-        hasher.finish().to_string()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn pages_listings_by_account() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        let listing_ids: Vec<ListingId> = ["1", "2", "3"]
+            .iter()
+            .map(|token_id| add_listing(&mut contract, "alice.near", vec![token(token_id)], None))
+            .collect();
+        add_listing(&mut contract, "bob.near", vec![token("4")], None);
+
+        let page: Vec<ListingId> = contract
+            .view_listings_by_account(account("alice.near"), Some(1), Some(5))
+            .into_iter()
+            .map(|listing| listing.id)
+            .collect();
+        assert_eq!(page, listing_ids[1..].to_vec());
+        assert_eq!(contract.view_listings(Some(3), Some(2)).len(), 1);
+        assert!(contract.view_listings_by_account(account("alice.near"), Some(3), None).is_empty());
+        assert_eq!(contract.listings_count(), 4);
+    }
+
+    #[test]
+    fn finds_wildcard_listings_for_concrete_tokens() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        add_listing(&mut contract, "alice.near", vec![token("*")], None);
+        add_listing(&mut contract, "bob.near", vec![token("2")], None);
+
+        assert_eq!(contract.view_listings_for_token(token("1"), None, None).len(), 1);
+        assert_eq!(contract.view_listings_for_token(token("2"), None, None).len(), 2);
+        let mut other_contract = token("1");
+        other_contract.sbt_contract_id = account("other-sbt.near");
+        assert!(contract.view_listings_for_token(other_contract, None, None).is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn pages_listings_for_a_token() {
+        let mut contract = setup_contract(&["alice.near", "bob.near", "carol.near"]);
+        add_listing(&mut contract, "alice.near", vec![token("1")], None);
+        add_listing(&mut contract, "bob.near", vec![token("2")], None);
+        add_listing(&mut contract, "carol.near", vec![token("1"), token("3")], None);
+
+        let page = contract.view_listings_for_token(token("1"), Some(1), Some(2));
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].account_id, account("carol.near"));
+        assert_eq!(contract.view_listings_for_token(token("1"), None, Some(1))[0].account_id, account("alice.near"));
+        assert!(contract.view_listings_for_token(token("1"), Some(3), None).is_empty());
+    }
+
+    #[test]
+    fn credits_offer_refunds_when_removing_a_listing() {
+        let mut contract = setup_contract(&["alice.near", "bob.near", "carol.near"]);
+        let listing_id = add_listing(&mut contract, "alice.near", vec![token("1")], Some(100));
+        add_offer(&mut contract, "bob.near", &listing_id, 100);
+        add_offer(&mut contract, "carol.near", &listing_id, 150);
+
+        set_caller("alice.near");
+        contract.remove_listing(listing_id.clone());
+
+        assert!(contract.view_listing(listing_id).is_none());
+        assert_eq!(contract.pending_payout(account("bob.near"), None).0, 100);
+        assert_eq!(contract.pending_payout(account("carol.near"), None).0, 150);
+        assert_eq!(contract.escrow_balance().0, 0);
+        assert!(contract.view_offers_made(account("bob.near"), None, None).is_empty());
+    }
+
+    fn verify_listing(contract: &mut Contract, tokens: Vec<SBTTokenLocator>, results: Vec<PromiseResult>) -> ListingId {
+        set_caller("alice.near");
+        contract.add_listing(tokens.clone(), None, None, None);
+        set_promise_results(results);
+        contract.on_listing_ownership_verified(SBTListing {
+            id: Contract::get_listing_id(&tokens, &account("alice.near")),
+            account_id: account("alice.near"),
+            tokens,
+            price: None,
+            ft_contract_id: None,
+            policy: ListingPolicy::Open,
+            sold: false,
+        })
+    }
+
+    #[test]
+    #[should_panic(expected = "SBT 1 on sbt.near is not owned by alice.near")]
+    fn rejects_listing_of_tokens_owned_by_others() {
+        let mut contract = setup_contract(&["alice.near"]);
+        verify_listing(&mut contract, vec![token("1")], vec![owned_by(&token("1"), "eve.near")]);
+    }
+
+    #[test]
+    #[should_panic(expected = "SBT 1 on sbt.near is not owned by alice.near")]
+    fn rejects_listing_of_unknown_tokens() {
+        let mut contract = setup_contract(&["alice.near"]);
+        verify_listing(&mut contract, vec![token("1")], vec![PromiseResult::Successful(b"null".to_vec())]);
+    }
+
+    #[test]
+    #[should_panic(expected = "SBT * on sbt.near is not owned by alice.near")]
+    fn rejects_wildcard_listing_without_tokens_of_the_issuer() {
+        let mut contract = setup_contract(&["alice.near"]);
+        verify_listing(&mut contract, vec![token("*")], vec![PromiseResult::Successful(b"[]".to_vec())]);
+    }
+
+    #[test]
+    #[should_panic(expected = "SBT 1 on sbt.near is not owned by alice.near")]
+    fn rejects_listing_when_the_registry_call_fails() {
+        let mut contract = setup_contract(&["alice.near"]);
+        verify_listing(&mut contract, vec![token("1")], vec![PromiseResult::Failed]);
+    }
+
+    #[test]
+    fn lists_tokens_reported_by_the_registry() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let listing_id = verify_listing(
+            &mut contract,
+            vec![token("1"), token("*")],
+            vec![owned_by(&token("1"), "alice.near"), owned_by(&token("*"), "alice.near")],
+        );
+        assert!(contract.view_listing(listing_id).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "SBT token ids must be numeric NEP-393 ids or *")]
+    fn rejects_non_numeric_token_ids() {
+        let mut contract = setup_contract(&["alice.near"]);
+        set_caller("alice.near");
+        contract.add_listing(vec![token("abc")], None, None, None);
+    }
+}
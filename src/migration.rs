@@ -0,0 +1,330 @@
+use crate::*;
+
+// layouts from before listing ids were hashed with sha256, kept only to read old state
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) struct LegacySBTListing {
+    pub id: ListingId,
+    pub account_id: AccountId,
+    pub tokens: Vec<SBTTokenLocator>,
+    pub price: Option<U128>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) struct LegacySBTListingOffer {
+    pub listing_id: ListingId,
+    pub offering_account_id: AccountId,
+    pub offered_price: Option<U128>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) struct LegacyContract {
+    pub contract_metadata: SBTPermissionsContractMetadata,
+    pub owner_id: AccountId,
+    // values were never signature checked and are not carried over
+    pub permissions_by_signature: LookupMap<Signature, Vec<u8>>,
+    pub permissions_for_token: LookupMap<(String, AccountId), LookupMap<TokenId, Vector<Signature>>>,
+    pub listings_by_id: UnorderedMap<ListingId, LegacySBTListing>,
+    pub listings_for_account: LookupMap<AccountId, Vector<ListingId>>,
+    pub offers_by_id: UnorderedMap<(ListingId, AccountId), LegacySBTListingOffer>,
+    pub offers_by_account: LookupMap<AccountId, UnorderedSet<ListingId>>,
+    pub offers_for_account: LookupMap<AccountId, UnorderedSet<(ListingId, AccountId)>>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ListingMigrationResult {
+    pub migrated: u64,
+    // legacy listings of a token set the lister had already listed, their offers are refunded
+    pub duplicates_removed: Vec<ListingId>,
+    pub remaining: u64,
+}
+
+#[near_bindgen]
+impl Contract {
+    // new listings, offers and permissions live under fresh prefixes, legacy listings and offers are
+    // moved over by migrate_listing_ids while creation stays paused
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate(chain_id: String, oracle_account_id: AccountId) -> Self {
+        let legacy: Option<LegacyContract> = env::state_read();
+        require!(legacy.is_some(), "No contract state to migrate");
+        let legacy = legacy.unwrap();
+
+        Self {
+            contract_metadata: legacy.contract_metadata,
+            chain_id,
+            oracle_account_id,
+            owner_id: legacy.owner_id,
+            pause_state: PauseState {
+                listings: true,
+                offers: true,
+                permissions: true,
+            },
+            permissions_by_signature: LookupMap::new(StorageKey::MigratedPermissionsBySignature),
+            permissions_for_token: legacy.permissions_for_token,
+            listings_by_id: UnorderedMap::new(StorageKey::MigratedListingsById),
+            listings_for_account: legacy.listings_for_account,
+            offers_by_id: UnorderedMap::new(StorageKey::MigratedOffersById),
+            offers_by_account: legacy.offers_by_account,
+            offers_for_account: legacy.offers_for_account,
+            legacy_listings: Some(legacy.listings_by_id),
+            legacy_offers: Some(legacy.offers_by_id),
+            ..Self::default()
+        }
+    }
+
+    // every batch drains what it migrates from the legacy map, so no listing is looked at twice
+    pub fn migrate_listing_ids(&mut self, limit: u64) -> ListingMigrationResult {
+        self.assert_owner();
+        require!(limit != 0, "Cannot provide limit of 0.");
+        require!(self.legacy_listings.is_some(), "There are no legacy listings to migrate");
+
+        let mut legacy_listings = self.legacy_listings.take().unwrap();
+        let mut result = ListingMigrationResult::default();
+        for _ in 0..limit {
+            if legacy_listings.is_empty() {
+                break;
+            }
+            let id = legacy_listings.keys_as_vector().get(legacy_listings.len() - 1).unwrap();
+            let legacy_listing = legacy_listings.remove(&id).unwrap();
+            self.migrate_legacy_listing(legacy_listing, &mut result);
+        }
+
+        result.remaining = legacy_listings.len();
+        if legacy_listings.is_empty() {
+            self.legacy_offers = None;
+        } else {
+            self.legacy_listings = Some(legacy_listings);
+        }
+        result
+    }
+
+    fn migrate_legacy_listing(&mut self, legacy_listing: LegacySBTListing, result: &mut ListingMigrationResult) {
+        let old_id = legacy_listing.id;
+        let account_id = legacy_listing.account_id;
+        let new_id = Self::get_listing_id(&legacy_listing.tokens, &account_id);
+
+        if let Some(mut accounts_listings) = self.listings_for_account.get(&account_id) {
+            if let Some(index) = accounts_listings.iter().position(|id| id == old_id) {
+                accounts_listings.swap_remove(index as u64);
+            }
+            if accounts_listings.is_empty() {
+                self.listings_for_account.remove(&account_id);
+            } else {
+                self.listings_for_account.insert(&account_id, &accounts_listings);
+            }
+        }
+        let offers = self.take_legacy_offers(&old_id, &account_id);
+
+        if self.listings_by_id.get(&new_id).is_some() {
+            log!("Legacy listing {} duplicates listing {}, refunding its offers", old_id, new_id);
+            for offer in offers {
+                if let Some(price) = offer.offered_price {
+                    self.credit_payout(&offer.offering_account_id, price.0, &None);
+                }
+            }
+            result.duplicates_removed.push(old_id);
+            return;
+        }
+
+        // listers and offerers pick up the storage of their migrated entries, as if created now
+        let listing = SBTListing {
+            id: new_id.clone(),
+            account_id,
+            tokens: legacy_listing.tokens,
+            price: legacy_listing.price,
+            ft_contract_id: None,
+            policy: ListingPolicy::Open,
+            sold: false,
+        };
+        self.insert_listing(&listing, true);
+        for offer in offers {
+            let offer = SBTListingOffer {
+                listing_id: new_id.clone(),
+                offering_account_id: offer.offering_account_id,
+                offered_price: offer.offered_price,
+                ft_contract_id: None,
+            };
+            self.insert_offer(&listing, &offer, true);
+        }
+        result.migrated += 1;
+    }
+
+    // drops the listing's entries from the offer indexes, including ones left behind by accepted offers
+    fn take_legacy_offers(&mut self, listing_id: &ListingId, lister_id: &AccountId) -> Vec<LegacySBTListingOffer> {
+        let mut offers_for_account = match self.offers_for_account.get(lister_id) {
+            Some(offers_for_account) => offers_for_account,
+            None => return Vec::new(),
+        };
+        let keys: Vec<(ListingId, AccountId)> = offers_for_account
+            .iter()
+            .filter(|(id, _)| id == listing_id)
+            .collect();
+
+        let legacy_offers = self.legacy_offers.as_mut().unwrap();
+        let mut offers = Vec::new();
+        for key in keys.iter() {
+            offers_for_account.remove(key);
+            if let Some(offer) = legacy_offers.remove(key) {
+                offers.push(offer);
+            }
+            if let Some(mut account_offers) = self.offers_by_account.get(&key.1) {
+                account_offers.remove(listing_id);
+                if account_offers.is_empty() {
+                    self.offers_by_account.remove(&key.1);
+                } else {
+                    self.offers_by_account.insert(&key.1, &account_offers);
+                }
+            }
+        }
+        if offers_for_account.is_empty() {
+            self.offers_for_account.remove(lister_id);
+        } else {
+            self.offers_for_account.insert(lister_id, &offers_for_account);
+        }
+        offers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    struct LegacyListingFixture<'a> {
+        id: &'a str,
+        lister: &'a str,
+        tokens: Vec<SBTTokenLocator>,
+        offers: Vec<(&'a str, Balance)>,
+    }
+
+    fn write_legacy_state(fixtures: Vec<LegacyListingFixture>) {
+        let mut legacy = LegacyContract {
+            contract_metadata: Contract::default().contract_metadata,
+            owner_id: account(OWNER),
+            permissions_by_signature: LookupMap::new(StorageKey::PermissionsBySignature),
+            permissions_for_token: LookupMap::new(StorageKey::PermissionsForToken),
+            listings_by_id: UnorderedMap::new(StorageKey::ListingsById),
+            listings_for_account: LookupMap::new(StorageKey::ListingsByAccount),
+            offers_by_id: UnorderedMap::new(StorageKey::OffersById),
+            offers_by_account: LookupMap::new(StorageKey::OffersByAccount),
+            offers_for_account: LookupMap::new(StorageKey::OffersForAccount),
+        };
+
+        for fixture in fixtures {
+            let id = fixture.id.to_string();
+            let lister = account(fixture.lister);
+            legacy.listings_by_id.insert(
+                &id,
+                &LegacySBTListing {
+                    id: id.clone(),
+                    account_id: lister.clone(),
+                    tokens: fixture.tokens,
+                    price: None,
+                },
+            );
+            let mut accounts_listings = legacy
+                .listings_for_account
+                .get(&lister)
+                .unwrap_or(Vector::new(StorageKey::ListingsForAccount { account_id: lister.clone() }));
+            accounts_listings.push(&id);
+            legacy.listings_for_account.insert(&lister, &accounts_listings);
+
+            for (offering_account, amount) in fixture.offers {
+                let offering_account = account(offering_account);
+                // a zero amount stands for an offer accepted before the upgrade, only its index entries remain
+                if amount > 0 {
+                    legacy.offers_by_id.insert(
+                        &(id.clone(), offering_account.clone()),
+                        &LegacySBTListingOffer {
+                            listing_id: id.clone(),
+                            offering_account_id: offering_account.clone(),
+                            offered_price: Some(U128(amount)),
+                        },
+                    );
+                }
+                let mut account_offers = legacy.offers_by_account.get(&offering_account).unwrap_or(
+                    UnorderedSet::new(StorageKey::OffersByAccountListing { account_id: offering_account.clone() }),
+                );
+                account_offers.insert(&id);
+                legacy.offers_by_account.insert(&offering_account, &account_offers);
+                let mut offers_for_account = legacy.offers_for_account.get(&lister).unwrap_or(
+                    UnorderedSet::new(StorageKey::OffersForAccountOffers { account_id: lister.clone() }),
+                );
+                offers_for_account.insert(&(id.clone(), offering_account));
+                legacy.offers_for_account.insert(&lister, &offers_for_account);
+            }
+        }
+        env::state_write(&legacy);
+    }
+
+    fn migrated_contract() -> Contract {
+        set_caller(MARKETPLACE);
+        write_legacy_state(vec![
+            LegacyListingFixture {
+                id: "111",
+                lister: "alice.near",
+                tokens: vec![token("1"), token("2")],
+                offers: vec![("bob.near", 100)],
+            },
+            LegacyListingFixture {
+                id: "222",
+                lister: "alice.near",
+                tokens: vec![token("2"), token("1")],
+                offers: vec![("carol.near", 50)],
+            },
+            LegacyListingFixture {
+                id: "333",
+                lister: "dave.near",
+                tokens: vec![token("3")],
+                offers: vec![("eve.near", 0)],
+            },
+        ]);
+        Contract::migrate("testnet".to_string(), account(ORACLE))
+    }
+
+    #[test]
+    fn migrates_legacy_listings_in_batches_and_drops_duplicates() {
+        let mut contract = migrated_contract();
+        assert!(contract.pause_state().listings && contract.pause_state().offers);
+
+        set_caller(OWNER);
+        let first = contract.migrate_listing_ids(2);
+        assert_eq!(first.remaining, 1);
+        let second = contract.migrate_listing_ids(10);
+        assert_eq!(second.remaining, 0);
+
+        assert_eq!(first.migrated + second.migrated, 2);
+        assert_eq!(first.duplicates_removed.len() + second.duplicates_removed.len(), 1);
+        assert_eq!(contract.listings_count(), 2);
+        let alice_listing = Contract::get_listing_id(&[token("1"), token("2")], &account("alice.near"));
+        assert!(contract.view_listing(alice_listing).is_some());
+        assert_eq!(contract.view_listings_by_account(account("alice.near"), None, None).len(), 1);
+        assert_eq!(contract.view_listings_for_token(token("3"), None, None).len(), 1);
+
+        // the duplicate's offer is refunded, the kept listing's offer stays in escrow
+        assert_eq!(contract.view_offers_received(account("alice.near"), None, None).len(), 1);
+        assert_eq!(contract.escrow_balance().0 + contract.pending_payout_total, 150);
+        assert!(contract.view_offers_received(account("dave.near"), None, None).is_empty());
+        assert!(contract.view_offers_made(account("eve.near"), None, None).is_empty());
+
+        contract.set_paused(PausableFeature::Listings, false);
+        assert!(!contract.pause_state().listings);
+    }
+
+    #[test]
+    #[should_panic(expected = "Legacy listings must be migrated before unpausing")]
+    fn stays_paused_until_legacy_listings_are_migrated() {
+        let mut contract = migrated_contract();
+        set_caller(OWNER);
+        contract.migrate_listing_ids(1);
+        contract.set_paused(PausableFeature::Offers, false);
+    }
+
+    #[test]
+    #[should_panic(expected = "There are no legacy listings to migrate")]
+    fn rejects_listing_migration_on_fresh_deployments() {
+        let mut contract = setup_contract(&[]);
+        contract.migrate_listing_ids(1);
+    }
+}
@@ -1,109 +1,709 @@
 use crate::*;
 
-// TODO: update/remove offer
+// TODO: update offer
 
-trait SBTMarketplaceOffers {
+pub trait SBTMarketplaceOffers {
     fn add_offer(&mut self, listing_id: ListingId);
 
-    fn view_offers(&self, account_id: AccountId) -> Vec<SBTListingOffer>;
+    fn view_offers_received(&self,
+        account_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<SBTListingOfferView>;
 
-    fn accept_offer(&mut self, listing_id: ListingId, permission: SBTPermission);
+    fn view_offers_made(&self,
+        account_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<SBTListingOfferView>;
+
+    fn view_offers_for_listing(&self,
+        listing_id: ListingId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<SBTListingOfferView>;
+
+    fn accept_offer(&mut self, listing_id: ListingId, permission: SBTPermission) -> Promise;
+
+    fn withdraw_offer(&mut self, listing_id: ListingId);
+
+    fn reject_offer(&mut self, listing_id: ListingId, offering_account_id: AccountId);
+
+    fn reject_all_offers(&mut self, listing_id: ListingId);
 }
 
 #[near_bindgen]
 impl SBTMarketplaceOffers for Contract {
     #[payable]
     fn add_offer(&mut self, listing_id: ListingId) {
+        self.place_offer(listing_id, env::predecessor_account_id(), env::attached_deposit(), None);
+    }
+
+    fn view_offers_received(&self,
+        account_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<SBTListingOfferView> {
+        match self.offers_for_account.get(&account_id) {
+            Some(offer_keys) => paginate(offer_keys.iter(), from_index, limit)
+                .iter()
+                .map(|key| self.offer_view(key))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn view_offers_made(&self,
+        account_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<SBTListingOfferView> {
+        match self.offers_by_account.get(&account_id) {
+            Some(listing_ids) => paginate(listing_ids.iter(), from_index, limit)
+                .into_iter()
+                .map(|listing_id| self.offer_view(&(listing_id, account_id.clone())))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn view_offers_for_listing(&self,
+        listing_id: ListingId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<SBTListingOfferView> {
+        match self.offers_for_listing.get(&listing_id) {
+            Some(offering_accounts) => paginate(offering_accounts.iter(), from_index, limit)
+                .into_iter()
+                .map(|offering_account| self.offer_view(&(listing_id.clone(), offering_account)))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn accept_offer(&mut self, listing_id: ListingId, permission: SBTPermission) -> Promise {
+        let id = listing_id;
+
+        {
+            require!(self.listings_by_id.get(&id).is_some(), "Listing does not exist");
+            let found = self.listings_by_id.get(&id).unwrap();
+            require!(found.account_id == env::predecessor_account_id(), "Cannot accept offer for another account's listing");
+            require!(!found.sold, "Listing has already been sold");
+            // listed tokens are unique, so equal lengths make this an exact match
+            require!(
+                permission.body.sbt_tokens.len() == found.tokens.len()
+                    && found.tokens.iter().all(|token| permission.body.sbt_tokens.contains(token)),
+                "Permission must cover exactly the tokens in the listing"
+            );
+        }
+
+        // offers are paid out in full, so the grant they buy cannot be partial or lapse
+        require!(
+            permission.body.grants(&PermissionScope::Full),
+            "Permission settling an offer must grant full access"
+        );
+        require!(
+            permission.body.valid_from.is_none() && permission.body.valid_until.is_none(),
+            "Permission settling an offer must not be time-limited"
+        );
+        require!(!permission.body.accounts.is_empty(), "At least 1 account must be given permission");
+        let unique_accounts: HashSet<&AccountId> = permission.body.accounts.iter().collect();
+        require!(unique_accounts.len() == permission.body.accounts.len(), "Permission lists an account more than once");
+
+        let missing_offers = self.accounts_without_offer(&id, &permission.body.accounts);
+        require!(
+            missing_offers.is_empty(),
+            format!("No offer on this listing from: {}", missing_offers.join(", "))
+        );
+
+        self.request_permission_validation(permission, Some(id))
+    }
+
+    fn withdraw_offer(&mut self, listing_id: ListingId) {
+        let offering_account = env::predecessor_account_id();
+        require!(
+            self.offers_by_id.get(&(listing_id.clone(), offering_account.clone())).is_some(),
+            "Offer does not exist"
+        );
+        let listing = self.listings_by_id.get(&listing_id).unwrap();
+
+        let offer = self.remove_offer(&listing, &offering_account);
+        emit_event("offer_withdrawn", &offer);
+        self.refund_offer(offer);
+    }
+
+    fn reject_offer(&mut self, listing_id: ListingId, offering_account_id: AccountId) {
+        let listing = self.owned_listing(&listing_id);
+        require!(
+            self.offers_by_id.get(&(listing_id, offering_account_id.clone())).is_some(),
+            "Offer does not exist"
+        );
+
+        let offer = self.remove_offer(&listing, &offering_account_id);
+        emit_event("offer_rejected", &offer);
+        self.refund_offer(offer);
+    }
+
+    fn reject_all_offers(&mut self, listing_id: ListingId) {
+        let listing = self.owned_listing(&listing_id);
+
+        for offering_account in self.offering_accounts(&listing) {
+            let offer = self.remove_offer(&listing, &offering_account);
+            emit_event("offer_rejected", &offer);
+            self.credit_refund(&offer);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    // NEP-141 receiver, msg carries the id of the listing the offer is for
+    pub fn ft_on_transfer(&mut self, sender_id: AccountId, amount: U128, msg: String) -> PromiseOrValue<U128> {
+        self.place_offer(msg, sender_id, amount.0, Some(env::predecessor_account_id()));
+        PromiseOrValue::Value(U128(0))
+    }
+
+    fn place_offer(
+        &mut self,
+        listing_id: ListingId,
+        offering_account: AccountId,
+        offered_price: Balance,
+        ft_contract_id: Option<AccountId>,
+    ) {
+        self.assert_not_paused(PausableFeature::Offers);
         let found_listing = self.listings_by_id.get(&listing_id);
         require!(found_listing.is_some(), "Listing does not exist");
         let listing = found_listing.unwrap();
+        require!(!listing.sold, "Listing has already been sold");
+        require!(listing.ft_contract_id == ft_contract_id, "Offer is not in the payment token of the listing");
 
-        let offering_account = &env::predecessor_account_id();
-        require!(listing.account_id != *offering_account, "Cannot submit offer for own listing");
+        require!(listing.account_id != offering_account, "Cannot submit offer for own listing");
         if let Some(ref all_listings_by_offering_account) = self.offers_by_account.get(&offering_account) {
             if all_listings_by_offering_account.contains(&listing_id) {
                 panic!("There is an offer in place for this listing by this account");
             }
         }
 
-        let offered_price = &env::attached_deposit();
         if let Some(ref expected_price) = &listing.price {
-            if offered_price < &u128::from(*expected_price) {
+            if offered_price < u128::from(*expected_price) {
                 panic!("Deposit does not match listed price");
             }
         }
 
         let offer = SBTListingOffer {
-            listing_id: listing_id.clone(),
-            offering_account_id: offering_account.clone(),
+            listing_id,
+            offering_account_id: offering_account,
             offered_price: {
-                if *offered_price > 0 { Some(U128(offered_price.clone())) } else { None }
-            }
+                if offered_price > 0 { Some(U128(offered_price)) } else { None }
+            },
+            ft_contract_id
         };
 
-        self.offers_by_id.insert(&(listing_id.clone(), offering_account.clone()), &offer);
+        self.insert_offer(&listing, &offer, false);
+        emit_event("offer_placed", &offer);
+    }
+
+    #[private]
+    pub fn on_offer_refunded(&mut self, offer: SBTListingOffer, #[callback_result] result: Result<(), PromiseError>) {
+        if result.is_ok() {
+            return;
+        }
+        let key = (offer.listing_id.clone(), offer.offering_account_id.clone());
+        match self.listings_by_id.get(&offer.listing_id) {
+            Some(listing) if self.offers_by_id.get(&key).is_none() => {
+                self.insert_offer(&listing, &offer, true);
+                emit_event("offer_placed", &offer);
+            }
+            _ => self.credit_refund(&offer),
+        }
+    }
+
+    // bulk removals credit refunds to the payout ledger, a transfer per offer would not fit in the gas of one call
+    pub(crate) fn credit_refund(&mut self, offer: &SBTListingOffer) {
+        if let Some(price) = offer.offered_price {
+            self.credit_payout(&offer.offering_account_id, price.0, &offer.ft_contract_id);
+        }
+    }
+
+    pub(crate) fn refund_offer(&mut self, offer: SBTListingOffer) {
+        if let Some(price) = offer.offered_price {
+            Self::transfer_payment(offer.offering_account_id.clone(), price.into(), &offer.ft_contract_id)
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(Gas(10 * TGAS))
+                        .on_offer_refunded(offer),
+                );
+        }
+    }
+
+    pub(crate) fn offering_accounts(&self, listing: &SBTListing) -> Vec<AccountId> {
+        self.offers_for_listing
+            .get(&listing.id)
+            .map(|offering_accounts| offering_accounts.to_vec())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn insert_offer(&mut self, listing: &SBTListing, offer: &SBTListingOffer, allow_overdraft: bool) {
+        let initial_storage = env::storage_usage();
+        let offering_account = &offer.offering_account_id;
+        self.offers_by_id.insert(&(listing.id.clone(), offering_account.clone()), offer);
+        if let (Some(price), None) = (offer.offered_price, &offer.ft_contract_id) {
+            self.escrow_balance += price.0;
+        }
         let mut account_offers = self
             .offers_by_account
-            .get(&offering_account)
+            .get(offering_account)
             .unwrap_or(UnorderedSet::new(StorageKey::OffersByAccountListing{account_id: offering_account.clone()}));
-        account_offers.insert(&listing_id);
-        self.offers_by_account.insert(&offering_account, &account_offers);
+        account_offers.insert(&listing.id);
+        self.offers_by_account.insert(offering_account, &account_offers);
         let mut offers_for_account = self
             .offers_for_account
             .get(&listing.account_id)
             .unwrap_or(UnorderedSet::new(StorageKey::OffersForAccountOffers{account_id: listing.account_id.clone()}));
-        offers_for_account.insert(&(listing_id.clone(), offering_account.clone()));
+        offers_for_account.insert(&(listing.id.clone(), offering_account.clone()));
         self.offers_for_account.insert(&listing.account_id, &offers_for_account);
+        let mut listing_offers = self
+            .offers_for_listing
+            .get(&listing.id)
+            .unwrap_or(UnorderedSet::new(StorageKey::OffersForListingAccounts{listing_id: listing.id.clone()}));
+        listing_offers.insert(offering_account);
+        self.offers_for_listing.insert(&listing.id, &listing_offers);
+        self.charge_storage(offering_account, initial_storage, allow_overdraft);
     }
 
-    fn view_offers(&self, account_id: AccountId) -> Vec<SBTListingOffer> {
-        let offer_keys = self.offers_for_account
-            .get(&account_id)
-            .unwrap_or(UnorderedSet::new(StorageKey::OffersForAccount))
-            .to_vec();
-        offer_keys.iter().map(|key| self.offers_by_id.get(&key).unwrap()).collect()
+    pub(crate) fn remove_offer(&mut self, listing: &SBTListing, offering_account: &AccountId) -> SBTListingOffer {
+        let initial_storage = env::storage_usage();
+        let offer = self
+            .offers_by_id
+            .remove(&(listing.id.clone(), offering_account.clone()))
+            .unwrap();
+        if let (Some(price), None) = (offer.offered_price, &offer.ft_contract_id) {
+            self.escrow_balance -= price.0;
+        }
+
+        let mut account_offers = self.offers_by_account.get(offering_account).unwrap();
+        account_offers.remove(&listing.id);
+        if account_offers.is_empty() {
+            self.offers_by_account.remove(offering_account);
+        } else {
+            self.offers_by_account.insert(offering_account, &account_offers);
+        }
+
+        let mut offers_for_account = self.offers_for_account.get(&listing.account_id).unwrap();
+        offers_for_account.remove(&(listing.id.clone(), offering_account.clone()));
+        if offers_for_account.is_empty() {
+            self.offers_for_account.remove(&listing.account_id);
+        } else {
+            self.offers_for_account.insert(&listing.account_id, &offers_for_account);
+        }
+
+        let mut listing_offers = self.offers_for_listing.get(&listing.id).unwrap();
+        listing_offers.remove(offering_account);
+        if listing_offers.is_empty() {
+            self.offers_for_listing.remove(&listing.id);
+        } else {
+            self.offers_for_listing.insert(&listing.id, &listing_offers);
+        }
+
+        self.charge_storage(offering_account, initial_storage, false);
+        offer
+    }
+
+    fn offer_view(&self, key: &(ListingId, AccountId)) -> SBTListingOfferView {
+        SBTListingOfferView {
+            offer: self.offers_by_id.get(key).unwrap(),
+            listing: self.listings_by_id.get(&key.0).unwrap(),
+        }
     }
 
-    fn accept_offer(&mut self, listing_id: ListingId, permission: SBTPermission) {
+    fn accounts_without_offer(&self, listing_id: &ListingId, accounts: &[AccountId]) -> Vec<String> {
+        accounts
+            .iter()
+            .filter(|account| self.offers_by_id.get(&(listing_id.clone(), (*account).clone())).is_none())
+            .map(|account| account.to_string())
+            .collect()
+    }
+
+    pub(crate) fn complete_accept_offer(&mut self, listing_id: ListingId, permission: SBTPermission, grantor: AccountId) {
         let id = listing_id;
 
-        let listing: SBTListing = {
-            require!(self.listings_by_id.get(&id).is_some(), "Listing does not exist");
-            let found = self.listings_by_id.get(&id).unwrap();
-            require!(found.account_id == env::predecessor_account_id(), "Cannot accept offer for another account's listing");
-            found
-        };
+        let listing = self.listings_by_id.get(&id).filter(|listing| !listing.sold);
+        if listing.is_none() {
+            log!("Listing {} is no longer available, permission discarded", id);
+            return;
+        }
+        let missing_offers = self.accounts_without_offer(&id, &permission.body.accounts);
+        if !missing_offers.is_empty() {
+            log!("Offers on listing {} by {} no longer exist, permission discarded", id, missing_offers.join(", "));
+            return;
+        }
+        let mut listing = listing.unwrap();
 
-        require!(permission.body.accounts.len() > 0, "At least 1 account must be given permission");
-        // TODO: handle accepting multiple offers at the same time
-        require!(permission.body.accounts.len() == 1, "WIP: Only 1 offer can be accepted");
-
-        let offering_account = permission.body.accounts[0].clone();
-        let offer : SBTListingOffer = {
-            let acc_offers = self.offers_by_account.get(&offering_account);
-            require!(acc_offers.is_some(), "Account does not have offers on this contract");
-            let acc_list_offers = acc_offers.unwrap();
-            require!(acc_list_offers.contains(&id), "Offer does not exist");
-            self.offers_by_id.get(&(id.clone(), offering_account.clone())).unwrap()
-        };
+        let offering_accounts = permission.body.accounts.clone();
+        let signature = permission.signature.clone();
+        self.store_permission(permission, grantor);
 
-        self.create_permission(permission);
+        for offering_account in offering_accounts.iter() {
+            let offer = self.remove_offer(&listing, offering_account);
+            emit_event(
+                "offer_accepted",
+                OfferAcceptedEventData { offer: &offer, lister_id: &listing.account_id, permission_signature: &signature },
+            );
+            self.pay_out_offer(&listing, &offer);
+        }
 
-        self.offers_by_id.remove(&(id.clone(), offering_account.clone()));
-        // TODO remove offers correctly from everywhere
+        if listing.policy == ListingPolicy::SingleSale {
+            for competing_account in self.offering_accounts(&listing) {
+                let competing_offer = self.remove_offer(&listing, &competing_account);
+                emit_event("offer_rejected", &competing_offer);
+                self.credit_refund(&competing_offer);
+            }
+            listing.sold = true;
+            self.listings_by_id.insert(&id, &listing);
+            emit_event("listing_updated", &listing);
+        }
+    }
 
+    fn pay_out_offer(&mut self, listing: &SBTListing, offer: &SBTListingOffer) {
         if let Some(ref price_json) = offer.offered_price {
-            let price = u128::from(*price_json);
-            // 80% of tx to owner
-            let owner_amount = price * 8 / 10;
-            let providers: Vec<AccountId> = listing.tokens.iter().map(|l| l.sbt_contract_id.clone()).collect();
-            // 15% of tx to the providers of each token, split evenly
-            let provider_amount = price * 3 / 20 / (providers.len() as u128);
-            for provider in providers.iter() {
-                Promise::new(provider.clone()).transfer(provider_amount);
+            let split = self.split_payment(listing, u128::from(*price_json));
+            for (provider, provider_amount) in split.provider_amounts {
+                self.credit_payout(&provider, provider_amount, &offer.ft_contract_id);
             }
-            Promise::new(listing.account_id).transfer(owner_amount);
-            // 5% of tx remains as marketplace fee
+            self.credit_payout(&listing.account_id, split.lister_amount, &offer.ft_contract_id);
+            self.credit_treasury(split.marketplace_amount + split.dust, &offer.ft_contract_id);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn listing_with_offer(contract: &mut Contract) -> ListingId {
+        let listing_id = add_listing(contract, "alice.near", vec![token("1")], Some(100));
+        add_offer(contract, "bob.near", &listing_id, 100);
+        listing_id
+    }
+
+    #[test]
+    #[should_panic(expected = "Permission settling an offer must not be time-limited")]
+    fn rejects_expiring_permission_for_accept() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        let listing_id = listing_with_offer(&mut contract);
+        let mut body = permission_body(vec![token("1")], &["bob.near"], 1);
+        body.valid_until = Some(U64(1));
+
+        set_caller("alice.near");
+        contract.accept_offer(listing_id, sign_permission(body, 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Permission settling an offer must grant full access")]
+    fn rejects_partially_scoped_permission_for_accept() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        let listing_id = listing_with_offer(&mut contract);
+        let mut body = permission_body(vec![token("1")], &["bob.near"], 1);
+        body.scopes = vec![PermissionScope::Ownership];
+
+        set_caller("alice.near");
+        contract.accept_offer(listing_id, sign_permission(body, 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Permission must cover exactly the tokens in the listing")]
+    fn rejects_permission_for_other_tokens_for_accept() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        let listing_id = listing_with_offer(&mut contract);
+
+        set_caller("alice.near");
+        contract.accept_offer(listing_id, sign_permission(permission_body(vec![token("2")], &["bob.near"], 1), 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Permission must cover exactly the tokens in the listing")]
+    fn rejects_permission_with_unlisted_tokens_for_accept() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        let listing_id = listing_with_offer(&mut contract);
+        let body = permission_body(vec![token("1"), token("99")], &["bob.near"], 1);
+
+        set_caller("alice.near");
+        contract.accept_offer(listing_id, sign_permission(body, 1));
+    }
+
+    #[test]
+    fn pages_offers_for_a_single_listing() {
+        let mut contract = setup_contract(&["alice.near", "bob.near", "carol.near"]);
+        let first = add_listing(&mut contract, "alice.near", vec![token("1")], Some(100));
+        let second = add_listing(&mut contract, "alice.near", vec![token("2")], Some(100));
+        add_offer(&mut contract, "bob.near", &first, 100);
+        add_offer(&mut contract, "carol.near", &first, 100);
+        add_offer(&mut contract, "bob.near", &second, 100);
+
+        let offers = contract.view_offers_for_listing(first.clone(), None, None);
+        assert_eq!(offers.len(), 2);
+        assert!(offers.iter().all(|offer| offer.listing.id == first));
+        assert_eq!(contract.view_offers_for_listing(first.clone(), Some(1), Some(1)).len(), 1);
+
+        set_caller("bob.near");
+        contract.withdraw_offer(first.clone());
+        let offers = contract.view_offers_for_listing(first.clone(), None, None);
+        assert_eq!(offers.len(), 1);
+        assert_eq!(offers[0].offer.offering_account_id, account("carol.near"));
+        assert_eq!(contract.view_offers_for_listing(second, None, None).len(), 1);
+    }
+
+    fn withdrawn_offer(contract: &mut Contract, listing_id: &ListingId) -> SBTListingOffer {
+        set_caller("bob.near");
+        contract.withdraw_offer(listing_id.clone());
+        SBTListingOffer {
+            listing_id: listing_id.clone(),
+            offering_account_id: account("bob.near"),
+            offered_price: Some(U128(100)),
+            ft_contract_id: None,
+        }
+    }
+
+    #[test]
+    fn restores_offer_when_refund_fails() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        let listing_id = listing_with_offer(&mut contract);
+        let offer = withdrawn_offer(&mut contract, &listing_id);
+        assert_eq!(contract.escrow_balance().0, 0);
+
+        set_caller(MARKETPLACE);
+        contract.on_offer_refunded(offer, Err(PromiseError::Failed));
+        let offers = contract.view_offers_for_listing(listing_id, None, None);
+        assert_eq!(offers.len(), 1);
+        assert_eq!(offers[0].offer.offering_account_id, account("bob.near"));
+        assert_eq!(contract.escrow_balance().0, 100);
+        assert_eq!(contract.pending_payout(account("bob.near"), None).0, 0);
+    }
+
+    #[test]
+    fn credits_payout_when_refund_fails_for_removed_listing() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        let listing_id = listing_with_offer(&mut contract);
+        let offer = withdrawn_offer(&mut contract, &listing_id);
+        set_caller("alice.near");
+        contract.remove_listing(listing_id.clone());
+
+        set_caller(MARKETPLACE);
+        contract.on_offer_refunded(offer, Err(PromiseError::Failed));
+        assert!(contract.view_offers_for_listing(listing_id, None, None).is_empty());
+        assert_eq!(contract.pending_payout(account("bob.near"), None).0, 100);
+        assert_eq!(contract.escrow_balance().0, 0);
+    }
+
+    #[test]
+    fn keeps_state_when_refund_succeeds() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        let listing_id = listing_with_offer(&mut contract);
+        let offer = withdrawn_offer(&mut contract, &listing_id);
+
+        set_caller(MARKETPLACE);
+        contract.on_offer_refunded(offer, Ok(()));
+        assert!(contract.view_offers_for_listing(listing_id, None, None).is_empty());
+        assert_eq!(contract.pending_payout(account("bob.near"), None).0, 0);
+    }
+
+    #[test]
+    fn credits_refunds_when_rejecting_all_offers() {
+        let mut contract = setup_contract(&["alice.near", "bob.near", "carol.near"]);
+        let listing_id = listing_with_offer(&mut contract);
+        add_offer(&mut contract, "carol.near", &listing_id, 120);
+
+        set_caller("alice.near");
+        contract.reject_all_offers(listing_id.clone());
+
+        assert!(contract.view_offers_for_listing(listing_id.clone(), None, None).is_empty());
+        assert!(contract.view_listing(listing_id).is_some());
+        assert_eq!(contract.pending_payout(account("bob.near"), None).0, 100);
+        assert_eq!(contract.pending_payout(account("carol.near"), None).0, 120);
+        assert_eq!(contract.escrow_balance().0, 0);
+    }
+
+    #[test]
+    fn marks_single_sale_listings_sold_and_credits_competing_offers() {
+        let mut contract = setup_contract(&["alice.near", "bob.near", "carol.near", "dave.near"]);
+        let listing_id =
+            add_listing_with(&mut contract, "alice.near", vec![token("1")], Some(100), ListingPolicy::SingleSale, None);
+        add_offer(&mut contract, "bob.near", &listing_id, 100);
+        add_offer(&mut contract, "carol.near", &listing_id, 110);
+        add_offer(&mut contract, "dave.near", &listing_id, 120);
+
+        let permission = sign_permission(permission_body(vec![token("1")], &["bob.near"], 1), 1);
+        accept_offer(&mut contract, "alice.near", &listing_id, &permission);
+
+        assert!(contract.view_listing(listing_id.clone()).unwrap().sold);
+        assert!(contract.view_offers_for_listing(listing_id, None, None).is_empty());
+        assert_eq!(contract.pending_payout(account("carol.near"), None).0, 110);
+        assert_eq!(contract.pending_payout(account("dave.near"), None).0, 120);
+        assert_eq!(contract.pending_payout(account("bob.near"), None).0, 0);
+        assert_eq!(contract.escrow_balance().0, 0);
+        assert!(contract.has_permission(token("1"), account("bob.near"), PermissionScope::Full));
+    }
+
+    #[test]
+    fn keeps_open_listings_and_competing_offers_after_accept() {
+        let mut contract = setup_contract(&["alice.near", "bob.near", "carol.near"]);
+        let listing_id = listing_with_offer(&mut contract);
+        add_offer(&mut contract, "carol.near", &listing_id, 110);
+
+        let permission = sign_permission(permission_body(vec![token("1")], &["bob.near"], 1), 1);
+        accept_offer(&mut contract, "alice.near", &listing_id, &permission);
+
+        assert!(!contract.view_listing(listing_id.clone()).unwrap().sold);
+        let offers = contract.view_offers_for_listing(listing_id.clone(), None, None);
+        assert_eq!(offers.len(), 1);
+        assert_eq!(offers[0].offer.offering_account_id, account("carol.near"));
+        assert_eq!(contract.pending_payout(account("carol.near"), None).0, 0);
+        assert_eq!(contract.escrow_balance().0, 110);
+
+        add_offer(&mut contract, "bob.near", &listing_id, 100);
+        assert_eq!(contract.view_offers_for_listing(listing_id, None, None).len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Listing has already been sold")]
+    fn rejects_offers_on_sold_listings() {
+        let mut contract = setup_contract(&["alice.near", "bob.near", "carol.near"]);
+        let listing_id =
+            add_listing_with(&mut contract, "alice.near", vec![token("1")], Some(100), ListingPolicy::SingleSale, None);
+        add_offer(&mut contract, "bob.near", &listing_id, 100);
+        let permission = sign_permission(permission_body(vec![token("1")], &["bob.near"], 1), 1);
+        accept_offer(&mut contract, "alice.near", &listing_id, &permission);
+
+        add_offer(&mut contract, "carol.near", &listing_id, 100);
+    }
+
+    #[test]
+    fn settles_every_offer_covered_by_a_multi_account_permission() {
+        let mut contract = setup_contract(&["alice.near", "bob.near", "carol.near"]);
+        let listing_id = add_listing(&mut contract, "alice.near", vec![token("1")], Some(1_000));
+        add_offer(&mut contract, "bob.near", &listing_id, 1_000);
+        add_offer(&mut contract, "carol.near", &listing_id, 2_000);
+
+        let permission = sign_permission(permission_body(vec![token("1")], &["bob.near", "carol.near"], 1), 1);
+        accept_offer(&mut contract, "alice.near", &listing_id, &permission);
+
+        assert!(contract.view_offers_for_listing(listing_id, None, None).is_empty());
+        assert!(contract.has_permission(token("1"), account("bob.near"), PermissionScope::Full));
+        assert!(contract.has_permission(token("1"), account("carol.near"), PermissionScope::Full));
+        assert_eq!(contract.pending_payout(account("alice.near"), None).0, 2_400);
+        assert_eq!(contract.pending_payout(account(SBT_CONTRACT), None).0, 450);
+        assert_eq!(contract.treasury_balance(None).0, 150);
+        assert_eq!(contract.escrow_balance().0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "No offer on this listing from: carol.near, dave.near")]
+    fn rejects_accepting_for_accounts_without_offers() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        let listing_id = listing_with_offer(&mut contract);
+        let body = permission_body(vec![token("1")], &["bob.near", "carol.near", "dave.near"], 1);
+
+        set_caller("alice.near");
+        contract.accept_offer(listing_id, sign_permission(body, 1));
+    }
+
+    #[test]
+    fn discards_accept_when_an_offer_is_withdrawn_before_the_oracle_answers() {
+        let mut contract = setup_contract(&["alice.near", "bob.near", "carol.near"]);
+        let listing_id = listing_with_offer(&mut contract);
+        add_offer(&mut contract, "carol.near", &listing_id, 100);
+        let permission = sign_permission(permission_body(vec![token("1")], &["bob.near", "carol.near"], 1), 1);
+        set_caller("alice.near");
+        contract.accept_offer(listing_id.clone(), clone_permission(&permission));
+
+        set_caller("bob.near");
+        contract.withdraw_offer(listing_id.clone());
+        oracle_answer(&mut contract, "alice.near", &permission, true);
+
+        assert!(!contract.has_permission(token("1"), account("carol.near"), PermissionScope::Full));
+        assert_eq!(contract.view_offers_for_listing(listing_id, None, None).len(), 1);
+        assert_eq!(contract.pending_payout(account("alice.near"), None).0, 0);
+    }
+
+    const FT: &str = "usdc.near";
+
+    fn ft_listing(contract: &mut Contract) -> ListingId {
+        add_listing_with(contract, "alice.near", vec![token("1")], Some(100), ListingPolicy::Open, Some(account(FT)))
+    }
+
+    fn transfer_ft_offer(contract: &mut Contract, ft_contract: &str, sender: &str, listing_id: &ListingId, amount: Balance) {
+        set_caller(ft_contract);
+        let unused = contract.ft_on_transfer(account(sender), U128(amount), listing_id.clone());
+        assert!(matches!(unused, PromiseOrValue::Value(U128(0))));
+    }
+
+    #[test]
+    fn places_ft_offers_outside_the_near_escrow() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        let listing_id = ft_listing(&mut contract);
+        transfer_ft_offer(&mut contract, FT, "bob.near", &listing_id, 150);
+
+        let offers = contract.view_offers_made(account("bob.near"), None, None);
+        assert_eq!(offers.len(), 1);
+        assert_eq!(offers[0].offer.ft_contract_id, Some(account(FT)));
+        assert_eq!(offers[0].offer.offered_price, Some(U128(150)));
+        assert_eq!(contract.escrow_balance().0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Offer is not in the payment token of the listing")]
+    fn rejects_ft_offers_in_another_token() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        let listing_id = ft_listing(&mut contract);
+        transfer_ft_offer(&mut contract, "fake-usdc.near", "bob.near", &listing_id, 150);
+    }
+
+    #[test]
+    #[should_panic(expected = "Offer is not in the payment token of the listing")]
+    fn rejects_near_offers_on_ft_listings() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        let listing_id = ft_listing(&mut contract);
+        add_offer(&mut contract, "bob.near", &listing_id, 150);
+    }
+
+    #[test]
+    fn settles_ft_offers_to_the_ft_payout_ledger() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        let listing_id = ft_listing(&mut contract);
+        transfer_ft_offer(&mut contract, FT, "bob.near", &listing_id, 1_000);
+
+        let permission = sign_permission(permission_body(vec![token("1")], &["bob.near"], 1), 1);
+        accept_offer(&mut contract, "alice.near", &listing_id, &permission);
+
+        assert_eq!(contract.pending_payout(account("alice.near"), Some(account(FT))).0, 800);
+        assert_eq!(contract.pending_payout(account(SBT_CONTRACT), Some(account(FT))).0, 150);
+        assert_eq!(contract.treasury_balance(Some(account(FT))).0, 50);
+        assert_eq!(contract.pending_payout(account("alice.near"), None).0, 0);
+        assert_eq!(contract.treasury_balance(None).0, 0);
+    }
+
+    #[test]
+    fn refunds_withdrawn_ft_offers_with_ft_transfer() {
+        let mut contract = setup_contract(&["alice.near", "bob.near"]);
+        let listing_id = ft_listing(&mut contract);
+        transfer_ft_offer(&mut contract, FT, "bob.near", &listing_id, 150);
+
+        set_caller("bob.near");
+        contract.withdraw_offer(listing_id.clone());
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        let refund = receipts.iter().find(|receipt| receipt.receiver_id == account(FT)).unwrap();
+        match &refund.actions[0] {
+            near_sdk::mock::VmAction::FunctionCall { function_name, args, deposit, .. } => {
+                assert_eq!(function_name, "ft_transfer");
+                assert_eq!(*deposit, 1);
+                let args: near_sdk::serde_json::Value = near_sdk::serde_json::from_slice(args).unwrap();
+                assert_eq!(args["receiver_id"], "bob.near");
+                assert_eq!(args["amount"], "150");
+            }
+            action => panic!("Unexpected refund action {:?}", action),
+        }
+        assert!(contract.view_offers_for_listing(listing_id, None, None).is_empty());
+    }
+}
@@ -1,5 +1,9 @@
 use crate::*;
 
+pub const SIGNATURE_PREFIX: &str = "ed25519:";
+// after this long without an oracle answer anyone can cancel a pending permission
+pub const PENDING_PERMISSION_TIMEOUT: u64 = 24 * 60 * 60 * 1_000_000_000;
+
 #[near_bindgen]
 impl Contract {
 
@@ -48,60 +52,345 @@ impl Contract {
         result
     }
 
+    fn active_permissions(&self, token: SBTTokenLocator, scope: Option<PermissionScope>) -> Vec<SBTPermission> {
+        let now = env::block_timestamp();
+        // signatures indexed before the state migration have no stored permission and are skipped
+        self.sbt_permissions_impl(token)
+            .iter()
+            .filter_map(|signature| self.permissions_by_signature.get(signature))
+            .filter(|permission| permission.body.is_active(now))
+            .filter(|permission| scope.as_ref().is_none_or(|scope| permission.body.grants(scope)))
+            .collect()
+    }
+
     pub fn sbt_permissions(
         &self,
         token: SBTTokenLocator,
+        scope: Option<PermissionScope>,
         from_index: Option<u64>,
         limit: Option<u64>,
     ) -> Vec<SBTPermission> {
-        let result = self.sbt_permissions_impl(token);
-
-        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
-        require!(limit != 0, "Cannot provide limit of 0.");
-        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
+        paginate(self.active_permissions(token, scope).into_iter(), from_index, limit)
+    }
 
-        if start_index > result.len() as u128 {
-            return Vec::new();
+    pub fn sbt_permissions_for_account(
+        &self,
+        account_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<SBTPermission> {
+        let now = env::block_timestamp();
+        // pages over the grantee's signatures, so inactive permissions leave a page short rather than loading them all
+        match self.permissions_for_account.get(&account_id) {
+            Some(signatures) => paginate(signatures.iter(), from_index, limit)
+                .iter()
+                .map(|signature| self.permissions_by_signature.get(signature).unwrap())
+                .filter(|permission| permission.body.is_active(now))
+                .collect(),
+            None => Vec::new(),
         }
+    }
 
-        result
+    pub fn sbt_permissions_count(&self, token: SBTTokenLocator, scope: Option<PermissionScope>) -> u64 {
+        self.active_permissions(token, scope).len() as u64
+    }
+
+    pub fn has_permission(&self, token: SBTTokenLocator, account_id: AccountId, scope: PermissionScope) -> bool {
+        self.active_permissions(token, Some(scope))
             .iter()
-            .skip(start_index as usize)
-            .take(limit)
-            .map(|signature| self.permissions_by_signature.get(&signature).unwrap())
-            .collect()
+            .any(|permission| permission.body.accounts.contains(&account_id))
+    }
+
+    pub fn sbt_permission_nonce(&self, public_key: PublicKey) -> Option<U64> {
+        self.permission_nonces.get(&public_key).map(U64)
+    }
+
+    fn verify_permission(&self, permission: &SBTPermission) {
+        require!(
+            permission.body.marketplace_contract_id == env::current_account_id(),
+            "Permission is signed for another marketplace contract"
+        );
+        require!(
+            permission.body.chain_id == self.chain_id,
+            "Permission is signed for another chain"
+        );
+        if let Some(last_nonce) = self.permission_nonces.get(&permission.public_key) {
+            require!(
+                u64::from(permission.body.nonce) > last_nonce,
+                "Permission nonce has already been used"
+            );
+        }
+        require!(!permission.body.scopes.is_empty(), "Permission must grant at least 1 scope");
+
+        if let Some(valid_until) = permission.body.valid_until {
+            require!(
+                u64::from(valid_until) > env::block_timestamp(),
+                "Permission has already expired"
+            );
+            if let Some(valid_from) = permission.body.valid_from {
+                require!(
+                    u64::from(valid_from) < u64::from(valid_until),
+                    "Permission validity window is empty"
+                );
+            }
+        }
+
+        let signature_bytes = {
+            let encoded = permission.signature.strip_prefix(SIGNATURE_PREFIX);
+            require!(encoded.is_some(), "Signature must be prefixed with ed25519:");
+            let decoded = near_sdk::bs58::decode(encoded.unwrap()).into_vec();
+            require!(decoded.is_ok(), "Signature is not valid base58");
+            decoded.unwrap()
+        };
+        let signature = ed25519_dalek::Signature::try_from(signature_bytes.as_slice());
+        require!(signature.is_ok(), "Signature must be 64 bytes long");
+
+        require!(
+            permission.public_key.curve_type() == CurveType::ED25519,
+            "Public key must be an ed25519 key"
+        );
+        let public_key = ed25519_dalek::PublicKey::from_bytes(&permission.public_key.as_bytes()[1..]);
+        require!(public_key.is_ok(), "Public key is not a valid ed25519 point");
+
+        let message = permission.body.try_to_vec().unwrap();
+        require!(
+            public_key
+                .unwrap()
+                .verify_strict(&message, &signature.unwrap())
+                .is_ok(),
+            "Signature does not match permission body"
+        );
+    }
+
+    pub fn sbt_pending_permission(&self, signature: Signature) -> Option<PendingPermission> {
+        self.pending_permissions.get(&signature)
+    }
+
+    // standalone permissions are checked for SBT ownership before the oracle is asked about the key
+    pub fn create_permission(&mut self, permission: SBTPermission) -> Promise {
+        require!(!permission.body.sbt_tokens.is_empty(), "Permission must include at least 1 token");
+        // owning one SBT of a contract says nothing about the others a wildcard would cover
+        require!(
+            permission.body.sbt_tokens.iter().all(|token| token.token_id != "*"),
+            "Standalone permissions must name each token, wildcards are not allowed"
+        );
+        require!(
+            permission.body.sbt_tokens.iter().all(|token| token.registry_token_id().is_some()),
+            "SBT token ids must be numeric NEP-393 ids"
+        );
+        let account_id = env::predecessor_account_id();
+        let tokens = permission.body.sbt_tokens.clone();
+        let signature = self.add_pending_permission(permission, None);
+
+        Self::ownership_checks(&tokens, &account_id).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(Gas(40 * TGAS))
+                .on_permission_ownership_verified(signature),
+        )
     }
 
-    pub fn sbt_permissions_count(&self, token: SBTTokenLocator) -> u64 {
-        self.sbt_permissions_impl(token).len() as u64
+    #[private]
+    pub fn on_permission_ownership_verified(&mut self, signature: Signature) {
+        let pending = match self.pending_permissions.get(&signature) {
+            Some(pending) => pending,
+            None => return,
+        };
+        let tokens = &pending.permission.body.sbt_tokens;
+        let owned = env::promise_results_count() == tokens.len() as u64
+            && tokens
+                .iter()
+                .enumerate()
+                .all(|(index, token)| Self::token_owned(index as u64, token, &pending.account_id));
+
+        if owned {
+            self.request_oracle_validation(&signature);
+        } else {
+            log!("{} does not own every SBT in permission {}, permission rejected", pending.account_id, signature);
+            self.remove_pending_permission(&signature);
+        }
     }
 
-    fn verify_permission(&self, _permission: &SBTPermission) {
-        // TODO: cross-contract call oracle to verify signature
-        //       and that permission body is matching it
+    pub fn cancel_pending_permission(&mut self, signature: Signature) {
+        let pending: PendingPermission = {
+            let found = self.pending_permissions.get(&signature);
+            require!(found.is_some(), "No pending permission with this signature");
+            found.unwrap()
+        };
+        require!(
+            pending.account_id == env::predecessor_account_id()
+                || env::block_timestamp() >= u64::from(pending.requested_at) + PENDING_PERMISSION_TIMEOUT,
+            "Only the requesting account can cancel a pending permission before it times out"
+        );
+        self.remove_pending_permission(&signature);
     }
 
-    pub fn create_permission(&mut self, permission: SBTPermission) {
+    pub(crate) fn request_permission_validation(
+        &mut self,
+        permission: SBTPermission,
+        listing_id: Option<ListingId>,
+    ) -> Promise {
+        let signature = self.add_pending_permission(permission, listing_id);
+        self.request_oracle_validation(&signature)
+    }
+
+    fn add_pending_permission(&mut self, permission: SBTPermission, listing_id: Option<ListingId>) -> Signature {
+        self.assert_not_paused(PausableFeature::Permissions);
         self.verify_permission(&permission);
 
-        if self
-            .permissions_by_signature
-            .contains_key(&permission.signature.clone())
-        {
-            panic!("Permission with signature already exists");
+        require!(
+            !self.permissions_by_signature.contains_key(&permission.signature),
+            "Permission with signature already exists"
+        );
+        require!(
+            self.pending_permissions.get(&permission.signature).is_none(),
+            "Permission with signature is already pending validation"
+        );
+        // the nonce is only used once the oracle confirms the key, until then it is reserved
+        let nonce_key = (permission.public_key.clone(), u64::from(permission.body.nonce));
+        require!(
+            !self.pending_permission_nonces.contains_key(&nonce_key),
+            "Permission nonce is already pending validation"
+        );
+
+        let account_id = env::predecessor_account_id();
+        let initial_storage = env::storage_usage();
+
+        let signature = permission.signature.clone();
+        self.pending_permission_nonces.insert(&nonce_key, &signature);
+        self.pending_permissions.insert(
+            &signature,
+            &PendingPermission {
+                permission,
+                account_id: account_id.clone(),
+                listing_id,
+                requested_at: U64(env::block_timestamp()),
+            },
+        );
+        self.charge_storage(&account_id, initial_storage, false);
+        signature
+    }
+
+    fn request_oracle_validation(&self, signature: &Signature) -> Promise {
+        let pending = self.pending_permissions.get(signature).unwrap();
+        ext_oracle::ext(self.oracle_account_id.clone())
+            .with_static_gas(Gas(20 * TGAS))
+            .request_validation(
+                pending.account_id,
+                String::from(&pending.permission.public_key),
+                Some(signature.clone()),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(10 * TGAS))
+                    .on_validation_requested(signature.clone()),
+            )
+    }
+
+    #[private]
+    pub fn on_validation_requested(
+        &mut self,
+        signature: Signature,
+        #[callback_result] result: Result<(), PromiseError>,
+    ) {
+        if result.is_err() {
+            log!("Oracle request failed, dropping pending permission {}", signature);
+            self.remove_pending_permission(&signature);
         }
+    }
+
+    pub fn on_sbt_marketplace_oracle_result(
+        &mut self,
+        account_id: AccountId,
+        public_key: String,
+        outcome: bool,
+        memo: Option<String>,
+    ) {
+        require!(
+            env::predecessor_account_id() == self.oracle_account_id,
+            "Only the oracle is allowed to call this method"
+        );
+        require!(memo.is_some(), "Oracle result does not reference a permission");
+        let signature = memo.unwrap();
+
+        let pending: PendingPermission = {
+            let found = self.pending_permissions.get(&signature);
+            require!(found.is_some(), "No pending permission with this signature");
+            found.unwrap()
+        };
+        require!(
+            pending.account_id == account_id
+                && String::from(&pending.permission.public_key) == public_key,
+            "Oracle result does not match the pending permission"
+        );
+        self.remove_pending_permission(&signature);
+
+        if !outcome {
+            log!("Public key {} does not belong to {}, permission rejected", public_key, account_id);
+            return;
+        }
+        // several nonces of a key can be pending at once, one confirmed first makes the lower ones stale
+        let nonce = u64::from(pending.permission.body.nonce);
+        if self.permission_nonces.get(&pending.permission.public_key).is_some_and(|last_nonce| nonce <= last_nonce) {
+            log!("Permission nonce {} is no longer above the last used nonce, permission rejected", nonce);
+            return;
+        }
+        // nonces are kept for good, so their storage is paid by the contract and never held against the account
+        self.permission_nonces.insert(&pending.permission.public_key, &nonce);
+
+        match pending.listing_id {
+            Some(listing_id) => self.complete_accept_offer(listing_id, pending.permission, pending.account_id),
+            None => self.store_permission(pending.permission, pending.account_id),
+        }
+    }
+
+    fn remove_pending_permission(&mut self, signature: &Signature) {
+        let initial_storage = env::storage_usage();
+        if let Some(pending) = self.pending_permissions.remove(signature) {
+            let body = &pending.permission.body;
+            self.pending_permission_nonces
+                .remove(&(pending.permission.public_key.clone(), u64::from(body.nonce)));
+            self.charge_storage(&pending.account_id, initial_storage, false);
+        }
+    }
 
+    // only reached from the oracle callback, so storage is charged even past the grantor's deposit
+    pub(crate) fn store_permission(&mut self, permission: SBTPermission, grantor: AccountId) {
+        let initial_storage = env::storage_usage();
         self.permissions_by_signature
             .insert(&permission.signature.clone(), &permission);
-
-        for token in permission.body.sbt_tokens {
-            let mut contract_permissions = self
-                .permissions_for_token
-                .get(&token.contract_key())
-                .unwrap_or(LookupMap::new(StorageKey::PermissionsForTokenContract {
-                    chain_id: token.chain_id.clone(),
-                    sbt_contract_id: token.sbt_contract_id.clone(),
+        self.permission_grantors
+            .insert(&permission.signature, &grantor);
+        if let Some(valid_until) = permission.body.valid_until {
+            self.permissions_by_expiry
+                .insert(&(valid_until.into(), permission.signature.clone()), &());
+        }
+        for account_id in permission.body.accounts.iter() {
+            let mut account_permissions = self
+                .permissions_for_account
+                .get(account_id)
+                .unwrap_or(UnorderedSet::new(StorageKey::PermissionsForAccountSignatures {
+                    account_id: account_id.clone(),
                 }));
+            account_permissions.insert(&permission.signature);
+            self.permissions_for_account.insert(account_id, &account_permissions);
+        }
+
+        // the per-contract bucket is shared by every grantor and never removed, so the contract pays for it
+        let mut shared_storage: StorageUsage = 0;
+        for token in permission.body.sbt_tokens.iter() {
+            let mut contract_permissions = match self.permissions_for_token.get(&token.contract_key()) {
+                Some(contract_permissions) => contract_permissions,
+                None => {
+                    let bucket_storage = env::storage_usage();
+                    let contract_permissions = LookupMap::new(StorageKey::PermissionsForTokenContract {
+                        chain_id: token.chain_id.clone(),
+                        sbt_contract_id: token.sbt_contract_id.clone(),
+                    });
+                    self.permissions_for_token.insert(&token.contract_key(), &contract_permissions);
+                    shared_storage += env::storage_usage() - bucket_storage;
+                    contract_permissions
+                }
+            };
 
             let mut token_permissions =
                 contract_permissions
@@ -117,5 +406,452 @@ impl Contract {
             self.permissions_for_token
                 .insert(&token.contract_key(), &contract_permissions);
         }
+
+        self.charge_storage(&grantor, initial_storage + shared_storage, true);
+        emit_event("permission_created", PermissionEventData::new(&permission, &grantor));
+    }
+
+    pub fn revoke_permission(&mut self, signature: Signature) {
+        let permission: SBTPermission = {
+            let found = self.permissions_by_signature.get(&signature);
+            require!(found.is_some(), "Permission does not exist");
+            found.unwrap()
+        };
+        require!(
+            self.permission_grantors.get(&signature) == Some(env::predecessor_account_id()),
+            "Only the granting account can revoke a permission"
+        );
+
+        let grantor = self.remove_permission(&permission);
+        emit_event("permission_revoked", PermissionEventData::new(&permission, &grantor));
+    }
+
+    pub fn prune_expired_permissions(&mut self, limit: u64) -> u64 {
+        require!(limit != 0, "Cannot provide limit of 0.");
+        let now = env::block_timestamp();
+
+        let expired: Vec<Signature> = self
+            .permissions_by_expiry
+            .iter()
+            .take_while(|((valid_until, _), _)| *valid_until <= now)
+            .take(limit as usize)
+            .map(|((_, signature), _)| signature)
+            .collect();
+
+        for signature in expired.iter() {
+            let permission = self.permissions_by_signature.get(signature).unwrap();
+            let grantor = self.remove_permission(&permission);
+            emit_event("permission_pruned", PermissionEventData::new(&permission, &grantor));
+        }
+
+        expired.len() as u64
+    }
+
+    fn remove_permission(&mut self, permission: &SBTPermission) -> AccountId {
+        let initial_storage = env::storage_usage();
+        let grantor = self.permission_grantors.get(&permission.signature).unwrap();
+
+        for token in permission.body.sbt_tokens.iter() {
+            let mut contract_permissions = match self.permissions_for_token.get(&token.contract_key()) {
+                Some(contract_permissions) => contract_permissions,
+                None => continue,
+            };
+            let mut token_permissions = match contract_permissions.get(&token.token_id) {
+                Some(token_permissions) => token_permissions,
+                None => continue,
+            };
+
+            if let Some(index) = token_permissions.iter().position(|s| s == permission.signature) {
+                token_permissions.swap_remove(index as u64);
+            }
+
+            if token_permissions.is_empty() {
+                contract_permissions.remove(&token.token_id);
+            } else {
+                contract_permissions.insert(&token.token_id, &token_permissions);
+            }
+            self.permissions_for_token
+                .insert(&token.contract_key(), &contract_permissions);
+        }
+
+        for account_id in permission.body.accounts.iter() {
+            if let Some(mut account_permissions) = self.permissions_for_account.get(account_id) {
+                account_permissions.remove(&permission.signature);
+                if account_permissions.is_empty() {
+                    self.permissions_for_account.remove(account_id);
+                } else {
+                    self.permissions_for_account.insert(account_id, &account_permissions);
+                }
+            }
+        }
+
+        self.permissions_by_signature.remove(&permission.signature);
+        self.permission_grantors.remove(&permission.signature);
+        if let Some(valid_until) = permission.body.valid_until {
+            self.permissions_by_expiry
+                .remove(&(valid_until.into(), permission.signature.clone()));
+        }
+
+        self.charge_storage(&grantor, initial_storage, false);
+        grantor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn pending_permission(contract: &mut Contract, grantor: &str) -> SBTPermission {
+        let permission = sign_permission(permission_body(vec![token("1")], &["bob.near"], 1), 1);
+        set_caller(grantor);
+        contract.create_permission(clone_permission(&permission));
+        permission
+    }
+
+    #[test]
+    fn stores_permission_after_ownership_and_oracle_confirm() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let permission = sign_permission(permission_body(vec![token("1")], &["bob.near"], 1), 1);
+        grant_permission(&mut contract, "alice.near", &permission);
+
+        assert!(contract.sbt_pending_permission(permission.signature.clone()).is_none());
+        assert!(contract.has_permission(token("1"), account("bob.near"), PermissionScope::Metadata));
+    }
+
+    #[test]
+    fn drops_pending_permission_for_unowned_tokens() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let permission = pending_permission(&mut contract, "alice.near");
+
+        set_promise_results(vec![owned_by(&token("1"), "eve.near")]);
+        contract.on_permission_ownership_verified(permission.signature.clone());
+
+        assert!(contract.sbt_pending_permission(permission.signature).is_none());
+    }
+
+    #[test]
+    fn drops_pending_permission_on_negative_oracle_outcome() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let permission = pending_permission(&mut contract, "alice.near");
+        set_promise_results(vec![owned_by(&token("1"), "alice.near")]);
+        contract.on_permission_ownership_verified(permission.signature.clone());
+
+        oracle_answer(&mut contract, "alice.near", &permission, false);
+
+        assert!(contract.sbt_pending_permission(permission.signature).is_none());
+        assert_eq!(contract.sbt_permissions_count(token("1"), None), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the oracle is allowed to call this method")]
+    fn rejects_oracle_result_from_other_accounts() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let permission = pending_permission(&mut contract, "alice.near");
+
+        set_caller("eve.near");
+        contract.on_sbt_marketplace_oracle_result(
+            account("alice.near"),
+            String::from(&permission.public_key),
+            true,
+            Some(permission.signature),
+        );
+    }
+
+    #[test]
+    fn permission_nonce_is_not_charged_to_the_grantor() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let registered_bytes = contract.storage_accounts.get(&account("alice.near")).unwrap().used_bytes;
+        let permission = pending_permission(&mut contract, "alice.near");
+        set_promise_results(vec![owned_by(&token("1"), "eve.near")]);
+        contract.on_permission_ownership_verified(permission.signature);
+
+        assert_eq!(contract.storage_accounts.get(&account("alice.near")).unwrap().used_bytes, registered_bytes);
+        set_caller_with_deposit("alice.near", 1);
+        assert!(contract.storage_unregister(None));
+    }
+
+    #[test]
+    fn rejected_front_run_does_not_use_the_nonce() {
+        let mut contract = setup_contract(&["alice.near", "eve.near"]);
+        let permission = pending_permission(&mut contract, "eve.near");
+        oracle_answer(&mut contract, "eve.near", &permission, false);
+        assert!(contract.sbt_permission_nonce(permission.public_key.clone()).is_none());
+
+        grant_permission(&mut contract, "alice.near", &permission);
+
+        assert_eq!(contract.sbt_permission_nonce(permission.public_key.clone()), Some(U64(1)));
+        assert!(contract.has_permission(token("1"), account("bob.near"), PermissionScope::Full));
+    }
+
+    #[test]
+    #[should_panic(expected = "Permission nonce is already pending validation")]
+    fn rejects_second_pending_permission_with_the_same_nonce() {
+        let mut contract = setup_contract(&["alice.near"]);
+        pending_permission(&mut contract, "alice.near");
+
+        let other = sign_permission(permission_body(vec![token("2")], &["bob.near"], 1), 1);
+        contract.create_permission(other);
+    }
+
+    #[test]
+    #[should_panic(expected = "Permission nonce has already been used")]
+    fn rejects_confirmed_nonce() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let permission = sign_permission(permission_body(vec![token("1")], &["bob.near"], 3), 1);
+        grant_permission(&mut contract, "alice.near", &permission);
+
+        set_caller("alice.near");
+        contract.create_permission(sign_permission(permission_body(vec![token("2")], &["bob.near"], 3), 1));
+    }
+
+    #[test]
+    fn requesting_account_can_cancel_pending_permission() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let permission = pending_permission(&mut contract, "alice.near");
+
+        set_caller("alice.near");
+        contract.cancel_pending_permission(permission.signature.clone());
+
+        assert!(contract.sbt_pending_permission(permission.signature).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "before it times out")]
+    fn others_cannot_cancel_pending_permission_before_timeout() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let permission = pending_permission(&mut contract, "alice.near");
+
+        set_caller("eve.near");
+        contract.cancel_pending_permission(permission.signature);
+    }
+
+    #[test]
+    fn anyone_can_cancel_pending_permission_after_timeout() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let permission = pending_permission(&mut contract, "alice.near");
+
+        set_caller_at("eve.near", PENDING_PERMISSION_TIMEOUT);
+        contract.cancel_pending_permission(permission.signature.clone());
+
+        assert!(contract.sbt_pending_permission(permission.signature).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Standalone permissions must name each token, wildcards are not allowed")]
+    fn rejects_wildcard_standalone_permissions() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let permission = sign_permission(permission_body(vec![token("*")], &["bob.near"], 1), 1);
+
+        set_caller("alice.near");
+        contract.create_permission(permission);
+    }
+
+    #[test]
+    fn drops_stale_nonce_confirmed_after_a_higher_one() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let higher = sign_permission(permission_body(vec![token("1")], &["bob.near"], 5), 1);
+        let lower = sign_permission(permission_body(vec![token("2")], &["bob.near"], 3), 1);
+        for permission in [&higher, &lower] {
+            set_caller("alice.near");
+            contract.create_permission(clone_permission(permission));
+            set_promise_results(vec![owned_by(&permission.body.sbt_tokens[0], "alice.near")]);
+            contract.on_permission_ownership_verified(permission.signature.clone());
+        }
+
+        oracle_answer(&mut contract, "alice.near", &higher, true);
+        oracle_answer(&mut contract, "alice.near", &lower, true);
+
+        assert_eq!(contract.sbt_permission_nonce(higher.public_key.clone()), Some(U64(5)));
+        assert!(contract.has_permission(token("1"), account("bob.near"), PermissionScope::Full));
+        assert!(contract.has_permission(token("1"), account("bob.near"), PermissionScope::Full));
+        assert!(contract.sbt_pending_permission(lower.signature).is_none());
+    }
+
+    fn create_signed(mut permission: SBTPermission, edit: impl FnOnce(&mut SBTPermission)) {
+        let mut contract = setup_contract(&["alice.near"]);
+        edit(&mut permission);
+        set_caller("alice.near");
+        contract.create_permission(permission);
+    }
+
+    fn signed_permission() -> SBTPermission {
+        sign_permission(permission_body(vec![token("1")], &["bob.near"], 1), 1)
+    }
+
+    #[test]
+    #[should_panic(expected = "Signature must be prefixed with ed25519:")]
+    fn rejects_signature_without_prefix() {
+        create_signed(signed_permission(), |permission| {
+            permission.signature = permission.signature.trim_start_matches(SIGNATURE_PREFIX).to_string();
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Signature is not valid base58")]
+    fn rejects_signature_that_is_not_base58() {
+        create_signed(signed_permission(), |permission| {
+            permission.signature = format!("{}0OIl", SIGNATURE_PREFIX);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Signature must be 64 bytes long")]
+    fn rejects_signature_of_the_wrong_length() {
+        create_signed(signed_permission(), |permission| {
+            permission.signature = format!("{}{}", SIGNATURE_PREFIX, near_sdk::bs58::encode([1u8; 32]).into_string());
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Public key must be an ed25519 key")]
+    fn rejects_non_ed25519_keys() {
+        create_signed(signed_permission(), |permission| {
+            permission.public_key = format!("secp256k1:{}", near_sdk::bs58::encode([1u8; 64]).into_string())
+                .parse()
+                .unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Signature does not match permission body")]
+    fn rejects_signature_over_another_body() {
+        create_signed(signed_permission(), |permission| {
+            permission.body.accounts = vec![account("eve.near")];
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Signature does not match permission body")]
+    fn rejects_signature_from_another_key() {
+        let other_key = sign_permission(permission_body(vec![token("1")], &["bob.near"], 1), 2).public_key;
+        create_signed(signed_permission(), |permission| {
+            permission.public_key = other_key;
+        });
+    }
+
+    fn used_bytes(contract: &Contract, account_id: &str) -> StorageUsage {
+        contract.storage_accounts.get(&account(account_id)).unwrap().used_bytes
+    }
+
+    #[test]
+    fn revoking_cleans_every_index_and_credits_the_grantor() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let registered_bytes = used_bytes(&contract, "alice.near");
+        let permission = sign_permission(permission_body(vec![token("1"), token("2")], &["bob.near", "carol.near"], 1), 1);
+        grant_permission(&mut contract, "alice.near", &permission);
+        assert!(used_bytes(&contract, "alice.near") > registered_bytes);
+
+        set_caller("alice.near");
+        contract.revoke_permission(permission.signature.clone());
+
+        assert_eq!(contract.sbt_permissions_count(token("1"), None), 0);
+        assert_eq!(contract.sbt_permissions_count(token("2"), None), 0);
+        assert!(contract.sbt_permissions_for_account(account("bob.near"), None, None).is_empty());
+        assert!(contract.sbt_permissions_for_account(account("carol.near"), None, None).is_empty());
+        assert_eq!(used_bytes(&contract, "alice.near"), registered_bytes);
+        set_caller_with_deposit("alice.near", 1);
+        assert!(contract.storage_unregister(None));
+    }
+
+    #[test]
+    fn revoking_clears_the_wildcard_bucket() {
+        let mut contract = setup_contract(&["alice.near"]);
+        // wildcard permissions are only stored when settling a wildcard listing
+        let permission = sign_permission(permission_body(vec![token("*")], &["bob.near"], 1), 1);
+        contract.store_permission(clone_permission(&permission), account("alice.near"));
+        assert!(contract.has_permission(token("5"), account("bob.near"), PermissionScope::Full));
+
+        set_caller("alice.near");
+        contract.revoke_permission(permission.signature);
+
+        assert!(!contract.has_permission(token("5"), account("bob.near"), PermissionScope::Full));
+        assert_eq!(contract.sbt_permissions_count(token("*"), None), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the granting account can revoke a permission")]
+    fn only_the_grantor_can_revoke() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let permission = signed_permission();
+        grant_permission(&mut contract, "alice.near", &permission);
+
+        set_caller("bob.near");
+        contract.revoke_permission(permission.signature);
+    }
+
+    fn timed_permission(nonce: u64, valid_from: Option<u64>, valid_until: Option<u64>) -> SBTPermission {
+        let mut body = permission_body(vec![token("1")], &["bob.near"], nonce);
+        body.valid_from = valid_from.map(U64);
+        body.valid_until = valid_until.map(U64);
+        sign_permission(body, 1)
+    }
+
+    #[test]
+    fn prunes_expired_permissions_in_batches() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let registered_bytes = used_bytes(&contract, "alice.near");
+        for (nonce, valid_until) in [(1, 100), (2, 200), (3, 300)] {
+            grant_permission(&mut contract, "alice.near", &timed_permission(nonce, None, Some(valid_until)));
+        }
+
+        set_caller_at("eve.near", 250);
+        assert_eq!(contract.sbt_permissions_count(token("1"), None), 1);
+        assert_eq!(contract.prune_expired_permissions(1), 1);
+        assert_eq!(contract.prune_expired_permissions(5), 1);
+        assert_eq!(contract.prune_expired_permissions(5), 0);
+        assert_eq!(contract.sbt_permissions_count(token("1"), None), 1);
+
+        set_caller_at("eve.near", 300);
+        assert_eq!(contract.prune_expired_permissions(5), 1);
+        assert_eq!(used_bytes(&contract, "alice.near"), registered_bytes);
+        assert!(contract.sbt_permissions_for_account(account("bob.near"), None, None).is_empty());
+    }
+
+    #[test]
+    fn filters_permissions_outside_their_validity_window() {
+        let mut contract = setup_contract(&["alice.near"]);
+        grant_permission(&mut contract, "alice.near", &timed_permission(1, Some(500), Some(1_000)));
+        grant_permission(&mut contract, "alice.near", &timed_permission(2, None, None));
+
+        set_caller_at("eve.near", 499);
+        assert_eq!(contract.sbt_permissions_count(token("1"), None), 1);
+        assert_eq!(contract.sbt_permissions(token("1"), None, None, None)[0].body.nonce, U64(2));
+
+        set_caller_at("eve.near", 500);
+        assert_eq!(contract.sbt_permissions_count(token("1"), None), 2);
+        assert_eq!(contract.sbt_permissions(token("1"), None, Some(1), None).len(), 1);
+
+        set_caller_at("eve.near", 1_000);
+        assert_eq!(contract.sbt_permissions_count(token("1"), None), 1);
+        assert!(contract.has_permission(token("1"), account("bob.near"), PermissionScope::Full));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot provide limit of 0.")]
+    fn rejects_pruning_with_zero_limit() {
+        let mut contract = setup_contract(&[]);
+        contract.prune_expired_permissions(0);
+    }
+
+    #[test]
+    fn pages_permissions_by_grantee() {
+        let mut contract = setup_contract(&["alice.near"]);
+        for nonce in 1..=3 {
+            let body = permission_body(vec![token(&nonce.to_string())], &["bob.near"], nonce);
+            grant_permission(&mut contract, "alice.near", &sign_permission(body, 1));
+        }
+        grant_permission(&mut contract, "alice.near", &timed_permission(4, Some(500), None));
+
+        let nonces: Vec<U64> = contract
+            .sbt_permissions_for_account(account("bob.near"), Some(1), Some(2))
+            .into_iter()
+            .map(|permission| permission.body.nonce)
+            .collect();
+        assert_eq!(nonces, vec![U64(2), U64(3)]);
+        // the not yet valid permission still takes its slot in the page
+        assert!(contract.sbt_permissions_for_account(account("bob.near"), Some(3), Some(1)).is_empty());
+        assert_eq!(contract.sbt_permissions_for_account(account("bob.near"), None, None).len(), 3);
+        assert!(contract.sbt_permissions_for_account(account("carol.near"), None, None).is_empty());
     }
 }
@@ -0,0 +1,226 @@
+use crate::*;
+
+// upper bound for the storage account record of a 64 character account id
+pub const STORAGE_ACCOUNT_BYTES: StorageUsage = 133;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct StorageAccount {
+    pub deposit: Balance,
+    pub used_bytes: StorageUsage,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalance {
+    pub total: U128,
+    pub available: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalanceBounds {
+    pub min: U128,
+    pub max: Option<U128>,
+}
+
+impl StorageAccount {
+    fn balance(&self) -> StorageBalance {
+        let used = self.used_bytes as Balance * env::storage_byte_cost();
+        StorageBalance {
+            total: U128(self.deposit),
+            available: U128(self.deposit.saturating_sub(used)),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    #[payable]
+    pub fn storage_deposit(&mut self, account_id: Option<AccountId>, registration_only: Option<bool>) -> StorageBalance {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let mut amount = env::attached_deposit();
+
+        let account = match self.storage_accounts.get(&account_id) {
+            Some(mut account) => {
+                if registration_only.unwrap_or(false) {
+                    if amount > 0 {
+                        Promise::new(env::predecessor_account_id()).transfer(amount);
+                    }
+                    return account.balance();
+                }
+                account.deposit += amount;
+                account
+            }
+            None => {
+                let min = self.storage_balance_bounds().min.0;
+                require!(amount >= min, "Deposit is less than the minimum storage balance");
+                if registration_only.unwrap_or(false) {
+                    Promise::new(env::predecessor_account_id()).transfer(amount - min);
+                    amount = min;
+                }
+
+                let initial_storage = env::storage_usage();
+                let account = StorageAccount { deposit: amount, used_bytes: 0 };
+                self.storage_accounts.insert(&account_id, &account);
+                StorageAccount {
+                    deposit: amount,
+                    used_bytes: env::storage_usage() - initial_storage,
+                }
+            }
+        };
+
+        self.storage_accounts.insert(&account_id, &account);
+        self.storage_deposit_total += amount;
+        account.balance()
+    }
+
+    #[payable]
+    pub fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        require!(env::attached_deposit() == 1, "Requires attached deposit of exactly 1 yoctoNEAR");
+        let account_id = env::predecessor_account_id();
+        let mut account = {
+            let found = self.storage_accounts.get(&account_id);
+            require!(found.is_some(), "Account is not registered for storage");
+            found.unwrap()
+        };
+
+        let available = account.balance().available.0;
+        let amount = amount.map(|amount| amount.0).unwrap_or(available);
+        require!(amount <= available, "Amount exceeds available storage balance");
+
+        account.deposit -= amount;
+        self.storage_accounts.insert(&account_id, &account);
+        self.storage_deposit_total -= amount;
+        if amount > 0 {
+            Promise::new(account_id).transfer(amount);
+        }
+        account.balance()
+    }
+
+    #[payable]
+    pub fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        require!(env::attached_deposit() == 1, "Requires attached deposit of exactly 1 yoctoNEAR");
+        require!(!force.unwrap_or(false), "Forced unregistration is not supported");
+        let account_id = env::predecessor_account_id();
+        let account = match self.storage_accounts.get(&account_id) {
+            Some(account) => account,
+            None => return false,
+        };
+
+        let initial_storage = env::storage_usage();
+        self.storage_accounts.remove(&account_id);
+        require!(
+            initial_storage - env::storage_usage() >= account.used_bytes,
+            "Account still has listings, offers or permissions stored"
+        );
+
+        self.storage_deposit_total -= account.deposit;
+        Promise::new(account_id).transfer(account.deposit + 1);
+        true
+    }
+
+    pub fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128(STORAGE_ACCOUNT_BYTES as Balance * env::storage_byte_cost()),
+            max: None,
+        }
+    }
+
+    pub fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.storage_accounts.get(&account_id).map(|account| account.balance())
+    }
+
+    pub(crate) fn assert_storage_registered(&self, account_id: &AccountId) {
+        require!(
+            self.storage_accounts.get(account_id).is_some(),
+            format!("{} is not registered for storage", account_id)
+        );
+    }
+
+    // attributes the storage change since initial_storage to account_id; callbacks that
+    // cannot be rolled back pass allow_overdraft so the account goes into storage debt instead
+    pub(crate) fn charge_storage(&mut self, account_id: &AccountId, initial_storage: StorageUsage, allow_overdraft: bool) {
+        let current_storage = env::storage_usage();
+        let mut account = match self.storage_accounts.get(account_id) {
+            Some(account) => account,
+            None => {
+                require!(allow_overdraft || current_storage <= initial_storage, format!("{} is not registered for storage", account_id));
+                return;
+            }
+        };
+
+        if current_storage >= initial_storage {
+            account.used_bytes += current_storage - initial_storage;
+            require!(
+                allow_overdraft || account.used_bytes as Balance * env::storage_byte_cost() <= account.deposit,
+                format!("Insufficient storage deposit for {}", account_id)
+            );
+        } else {
+            account.used_bytes = account.used_bytes.saturating_sub(initial_storage - current_storage);
+        }
+        self.storage_accounts.insert(account_id, &account);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn used_bytes(contract: &Contract, account_id: &str) -> StorageUsage {
+        contract.storage_accounts.get(&account(account_id)).unwrap().used_bytes
+    }
+
+    #[test]
+    fn charges_and_credits_listing_storage() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let registered_bytes = used_bytes(&contract, "alice.near");
+        let listing_id = add_listing(&mut contract, "alice.near", vec![token("1")], Some(100));
+        let listed_bytes = used_bytes(&contract, "alice.near");
+        assert!(listed_bytes > registered_bytes);
+        let balance = contract.storage_balance_of(account("alice.near")).unwrap();
+        assert_eq!(
+            balance.available.0,
+            STORAGE_DEPOSIT - listed_bytes as Balance * env::storage_byte_cost()
+        );
+
+        set_caller("alice.near");
+        contract.remove_listing(listing_id);
+        assert_eq!(used_bytes(&contract, "alice.near"), registered_bytes);
+    }
+
+    #[test]
+    #[should_panic(expected = "Insufficient storage deposit for bob.near")]
+    fn rejects_offers_beyond_the_storage_deposit() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let listing_id = add_listing(&mut contract, "alice.near", vec![token("1")], None);
+        let min = contract.storage_balance_bounds().min.0;
+        set_caller_with_deposit("bob.near", min);
+        contract.storage_deposit(None, None);
+
+        add_offer(&mut contract, "bob.near", &listing_id, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Account still has listings, offers or permissions stored")]
+    fn rejects_unregistering_with_stored_listings() {
+        let mut contract = setup_contract(&["alice.near"]);
+        add_listing(&mut contract, "alice.near", vec![token("1")], None);
+
+        set_caller_with_deposit("alice.near", 1);
+        contract.storage_unregister(None);
+    }
+
+    #[test]
+    fn unregisters_once_listings_are_removed() {
+        let mut contract = setup_contract(&["alice.near"]);
+        let listing_id = add_listing(&mut contract, "alice.near", vec![token("1")], None);
+        set_caller("alice.near");
+        contract.remove_listing(listing_id);
+
+        set_caller_with_deposit("alice.near", 1);
+        assert!(contract.storage_unregister(None));
+        assert!(contract.storage_balance_of(account("alice.near")).is_none());
+        assert_eq!(contract.storage_deposit_total, 0);
+    }
+}
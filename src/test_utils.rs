@@ -0,0 +1,175 @@
+use crate::*;
+use ed25519_dalek::{Keypair, SecretKey, Signer};
+use near_sdk::test_utils::VMContextBuilder;
+use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig};
+
+pub const MARKETPLACE: &str = "market.near";
+pub const ORACLE: &str = "oracle_contract";
+pub const OWNER: &str = "default";
+pub const SBT_CONTRACT: &str = "sbt.near";
+pub const STORAGE_DEPOSIT: Balance = 1_000_000_000_000_000_000_000_000;
+
+pub fn account(account_id: &str) -> AccountId {
+    account_id.parse().unwrap()
+}
+
+pub fn context(predecessor: &str, deposit: Balance, timestamp: u64) -> VMContextBuilder {
+    let mut builder = VMContextBuilder::new();
+    builder
+        .current_account_id(account(MARKETPLACE))
+        .predecessor_account_id(account(predecessor))
+        .attached_deposit(deposit)
+        .block_timestamp(timestamp)
+        .account_balance(1_000 * STORAGE_DEPOSIT);
+    builder
+}
+
+pub fn set_caller(predecessor: &str) {
+    testing_env!(context(predecessor, 0, 0).build());
+}
+
+pub fn set_caller_with_deposit(predecessor: &str, deposit: Balance) {
+    testing_env!(context(predecessor, deposit, 0).build());
+}
+
+pub fn set_caller_at(predecessor: &str, timestamp: u64) {
+    testing_env!(context(predecessor, 0, timestamp).build());
+}
+
+pub fn set_promise_results(results: Vec<PromiseResult>) {
+    testing_env!(
+        context(MARKETPLACE, 0, 0).build(),
+        VMConfig::test(),
+        RuntimeFeesConfig::test(),
+        Default::default(),
+        results
+    );
+}
+
+// registry answer for a token owned by owner_id, a wildcard token lists the tokens owner_id holds of the issuer
+pub fn owned_by(token: &SBTTokenLocator, owner_id: &str) -> PromiseResult {
+    let metadata = json!({ "class": 1, "issued_at": null, "expires_at": null, "reference": null, "reference_hash": null });
+    let result = if token.token_id == "*" {
+        json!([[token.sbt_contract_id, [{ "token": 1, "metadata": metadata }]]])
+    } else {
+        json!({ "token": token.registry_token_id().unwrap(), "owner": owner_id, "metadata": metadata })
+    };
+    PromiseResult::Successful(result.to_string().into_bytes())
+}
+
+pub fn token(token_id: &str) -> SBTTokenLocator {
+    SBTTokenLocator {
+        chain_id: "testnet".to_string(),
+        sbt_contract_id: account(SBT_CONTRACT),
+        token_id: token_id.to_string(),
+    }
+}
+
+pub fn setup_contract(registered: &[&str]) -> Contract {
+    set_caller(OWNER);
+    let mut contract = Contract::default();
+    for account_id in registered {
+        set_caller_with_deposit(account_id, STORAGE_DEPOSIT);
+        contract.storage_deposit(None, None);
+    }
+    contract
+}
+
+fn keypair(seed: u8) -> Keypair {
+    let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+    let public = (&secret).into();
+    Keypair { secret, public }
+}
+
+pub fn permission_body(tokens: Vec<SBTTokenLocator>, accounts: &[&str], nonce: u64) -> PermissionBody {
+    PermissionBody {
+        marketplace_contract_id: account(MARKETPLACE),
+        chain_id: "testnet".to_string(),
+        nonce: U64(nonce),
+        sbt_tokens: tokens,
+        accounts: accounts.iter().map(|account_id| account(account_id)).collect(),
+        scopes: vec![PermissionScope::Full],
+        valid_from: None,
+        valid_until: None,
+    }
+}
+
+pub fn sign_permission(body: PermissionBody, key_seed: u8) -> SBTPermission {
+    let keypair = keypair(key_seed);
+    let signature = keypair.sign(&body.try_to_vec().unwrap());
+    SBTPermission {
+        body,
+        signature: format!("{}{}", SIGNATURE_PREFIX, near_sdk::bs58::encode(signature.to_bytes()).into_string()),
+        public_key: format!("ed25519:{}", near_sdk::bs58::encode(keypair.public.as_bytes()).into_string())
+            .parse()
+            .unwrap(),
+    }
+}
+
+pub fn clone_permission(permission: &SBTPermission) -> SBTPermission {
+    SBTPermission::try_from_slice(&permission.try_to_vec().unwrap()).unwrap()
+}
+
+pub fn oracle_answer(contract: &mut Contract, account_id: &str, permission: &SBTPermission, outcome: bool) {
+    set_caller(ORACLE);
+    contract.on_sbt_marketplace_oracle_result(
+        account(account_id),
+        String::from(&permission.public_key),
+        outcome,
+        Some(permission.signature.clone()),
+    );
+}
+
+// runs a standalone permission through the ownership check and the oracle
+pub fn grant_permission(contract: &mut Contract, grantor: &str, permission: &SBTPermission) {
+    set_caller(grantor);
+    contract.create_permission(clone_permission(permission));
+    set_promise_results(
+        permission
+            .body
+            .sbt_tokens
+            .iter()
+            .map(|token| owned_by(token, grantor))
+            .collect(),
+    );
+    contract.on_permission_ownership_verified(permission.signature.clone());
+    oracle_answer(contract, grantor, permission, true);
+}
+
+pub fn add_listing(contract: &mut Contract, lister: &str, tokens: Vec<SBTTokenLocator>, price: Option<Balance>) -> ListingId {
+    add_listing_with(contract, lister, tokens, price, ListingPolicy::Open, None)
+}
+
+pub fn add_listing_with(
+    contract: &mut Contract,
+    lister: &str,
+    tokens: Vec<SBTTokenLocator>,
+    price: Option<Balance>,
+    policy: ListingPolicy,
+    ft_contract_id: Option<AccountId>,
+) -> ListingId {
+    set_caller(lister);
+    contract.add_listing(tokens.clone(), price.map(U128), Some(policy), ft_contract_id.clone());
+    set_promise_results(tokens.iter().map(|token| owned_by(token, lister)).collect());
+    contract.on_listing_ownership_verified(SBTListing {
+        id: Contract::get_listing_id(&tokens, &account(lister)),
+        account_id: account(lister),
+        tokens,
+        price: price.map(U128),
+        ft_contract_id,
+        policy,
+        sold: false,
+    })
+}
+
+pub fn add_offer(contract: &mut Contract, offering_account: &str, listing_id: &ListingId, amount: Balance) {
+    set_caller_with_deposit(offering_account, amount);
+    contract.add_offer(listing_id.clone());
+}
+
+// accepts offers with a permission and lets the oracle confirm the key
+pub fn accept_offer(contract: &mut Contract, lister: &str, listing_id: &ListingId, permission: &SBTPermission) {
+    set_caller(lister);
+    contract.accept_offer(listing_id.clone(), clone_permission(permission));
+    oracle_answer(contract, lister, permission, true);
+}
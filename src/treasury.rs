@@ -0,0 +1,179 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    pub(crate) fn transfer_payment(receiver_id: AccountId, amount: Balance, ft_contract_id: &Option<AccountId>) -> Promise {
+        match ft_contract_id {
+            Some(ft_contract_id) => ext_ft::ext(ft_contract_id.clone())
+                .with_attached_deposit(1)
+                .with_static_gas(Gas(10 * TGAS))
+                .ft_transfer(receiver_id, U128(amount), None),
+            None => Promise::new(receiver_id).transfer(amount),
+        }
+    }
+
+    pub fn treasury_balance(&self, ft_contract_id: Option<AccountId>) -> U128 {
+        U128(self.treasury_balances.get(&ft_contract_id).unwrap_or(0))
+    }
+
+    pub fn escrow_balance(&self) -> U128 {
+        U128(self.escrow_balance)
+    }
+
+    pub fn withdraw_fees(&mut self, amount: U128, receiver: AccountId, ft_contract_id: Option<AccountId>) -> Promise {
+        self.assert_owner();
+        let amount = amount.0;
+        require!(amount > 0, "Cannot withdraw 0");
+        let treasury_balance = self.treasury_balances.get(&ft_contract_id).unwrap_or(0);
+        require!(amount <= treasury_balance, "Amount exceeds accrued fees");
+
+        if ft_contract_id.is_none() {
+            let storage_cost = env::storage_usage() as Balance * env::storage_byte_cost();
+            let available = env::account_balance()
+                .saturating_sub(storage_cost)
+                .saturating_sub(self.escrow_balance)
+                .saturating_sub(self.pending_payout_total)
+                .saturating_sub(self.storage_deposit_total);
+            require!(amount <= available, "Withdrawal would touch escrowed, owed or staked funds");
+        }
+
+        self.treasury_balances.insert(&ft_contract_id, &(treasury_balance - amount));
+        Self::transfer_payment(receiver, amount, &ft_contract_id).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(Gas(10 * TGAS))
+                .on_fees_withdrawn(U128(amount), ft_contract_id),
+        )
+    }
+
+    #[private]
+    pub fn on_fees_withdrawn(
+        &mut self,
+        amount: U128,
+        ft_contract_id: Option<AccountId>,
+        #[callback_result] result: Result<(), PromiseError>,
+    ) {
+        if result.is_err() {
+            log!("Fee withdrawal of {} failed, restoring treasury balance", amount.0);
+            self.credit_treasury(amount.0, &ft_contract_id);
+        }
+    }
+
+    pub fn pending_payout(&self, account_id: AccountId, ft_contract_id: Option<AccountId>) -> U128 {
+        U128(self.pending_payouts.get(&(account_id, ft_contract_id)).unwrap_or(0))
+    }
+
+    pub fn claim_payout(&mut self, ft_contract_id: Option<AccountId>) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let amount = self
+            .pending_payouts
+            .remove(&(account_id.clone(), ft_contract_id.clone()))
+            .unwrap_or(0);
+        require!(amount > 0, "No pending payout");
+        if ft_contract_id.is_none() {
+            self.pending_payout_total -= amount;
+        }
+        emit_event(
+            "payout_claimed",
+            PayoutEventData { account_id: &account_id, amount: U128(amount), ft_contract_id: &ft_contract_id },
+        );
+
+        Self::transfer_payment(account_id.clone(), amount, &ft_contract_id).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(Gas(10 * TGAS))
+                .on_payout_claimed(account_id, U128(amount), ft_contract_id),
+        )
+    }
+
+    #[private]
+    pub fn on_payout_claimed(
+        &mut self,
+        account_id: AccountId,
+        amount: U128,
+        ft_contract_id: Option<AccountId>,
+        #[callback_result] result: Result<(), PromiseError>,
+    ) {
+        if result.is_err() {
+            log!("Payout of {} to {} failed, restoring pending balance", amount.0, account_id);
+            self.credit_payout(&account_id, amount.0, &ft_contract_id);
+        }
+    }
+
+    pub(crate) fn credit_payout(&mut self, account_id: &AccountId, amount: Balance, ft_contract_id: &Option<AccountId>) {
+        if amount == 0 {
+            return;
+        }
+        let key = (account_id.clone(), ft_contract_id.clone());
+        let pending = self.pending_payouts.get(&key).unwrap_or(0);
+        self.pending_payouts.insert(&key, &(pending + amount));
+        if ft_contract_id.is_none() {
+            self.pending_payout_total += amount;
+        }
+        emit_event(
+            "payout_credited",
+            PayoutEventData { account_id, amount: U128(amount), ft_contract_id },
+        );
+    }
+
+    pub(crate) fn credit_treasury(&mut self, amount: Balance, ft_contract_id: &Option<AccountId>) {
+        if amount == 0 {
+            return;
+        }
+        let balance = self.treasury_balances.get(ft_contract_id).unwrap_or(0);
+        self.treasury_balances.insert(ft_contract_id, &(balance + amount));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn restores_treasury_when_fee_withdrawal_fails() {
+        let mut contract = setup_contract(&[]);
+        contract.credit_treasury(500, &None);
+        set_caller(OWNER);
+        contract.withdraw_fees(U128(300), account(OWNER), None);
+        assert_eq!(contract.treasury_balance(None).0, 200);
+
+        set_caller(MARKETPLACE);
+        contract.on_fees_withdrawn(U128(300), None, Err(PromiseError::Failed));
+        assert_eq!(contract.treasury_balance(None).0, 500);
+    }
+
+    #[test]
+    fn keeps_treasury_debited_when_fee_withdrawal_succeeds() {
+        let mut contract = setup_contract(&[]);
+        contract.credit_treasury(500, &None);
+        set_caller(OWNER);
+        contract.withdraw_fees(U128(300), account(OWNER), None);
+
+        set_caller(MARKETPLACE);
+        contract.on_fees_withdrawn(U128(300), None, Ok(()));
+        assert_eq!(contract.treasury_balance(None).0, 200);
+    }
+
+    #[test]
+    fn restores_pending_payout_when_claim_fails() {
+        let mut contract = setup_contract(&[]);
+        contract.credit_payout(&account("bob.near"), 100, &None);
+        set_caller("bob.near");
+        contract.claim_payout(None);
+        assert_eq!(contract.pending_payout(account("bob.near"), None).0, 0);
+
+        set_caller(MARKETPLACE);
+        contract.on_payout_claimed(account("bob.near"), U128(100), None, Err(PromiseError::Failed));
+        assert_eq!(contract.pending_payout(account("bob.near"), None).0, 100);
+        assert_eq!(contract.pending_payout_total, 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "No pending payout")]
+    fn rejects_claiming_a_payout_twice() {
+        let mut contract = setup_contract(&[]);
+        contract.credit_payout(&account("bob.near"), 100, &None);
+        set_caller("bob.near");
+        contract.claim_payout(None);
+        contract.claim_payout(None);
+    }
+}